@@ -6,6 +6,9 @@ fn main() {
     // Assert we haven't travelled back in time
     assert!(version().unwrap().major >= 1);
 
+    // Declare the channel cfg flags we may set below
+    println!("cargo:rustc-check-cfg=cfg(stable, beta, nightly, dev)");
+
     // Set cfg flags depending on release channel
     match version_meta().unwrap().channel {
         Channel::Stable => {
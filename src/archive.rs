@@ -0,0 +1,616 @@
+//! Read-only `Map`s that are used directly from a byte buffer.
+//!
+//! An archive is written from a `Map` with [`to_vec()`] (or [`write()`]), and can later be used without deserialising through an [`ArchivedMap`].
+//! This allows lookup tables to be embedded in a binary with `include_bytes!()`, or memory-mapped from a file.
+//!
+//! Only keys and values with a fixed-size encoding (see [`Archive`]) can be archived.
+//! Each page of the `Map` is stored as-is, with every one of its slots, so a lookup is the same single collapse-index dereference per page as it is in `Map`.
+//!
+//! # Format
+//! All integers are little-endian.
+//! * The header: the magic bytes `SMAR`, a format [`VERSION`] byte, 3 zero bytes, then the key size, the value size and the number of pages as `u32`s.
+//! * Each page: a 256-bit bitmap of which slots are occupied, followed by 256 slots each containing an encoded key then an encoded value (or zeros if the slot is not occupied.)
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! use smallmap::archive::{self, ArchivedMap};
+//!
+//! let map: Map<u32, u64> = (0..1000).map(|x| (x, u64::from(x) * 2)).collect();
+//! let bytes = archive::to_vec(&map);
+//!
+//! let archived = ArchivedMap::<u32, u64>::new(&bytes[..]).unwrap();
+//! assert_eq!(archived.get(&500), Some(1000));
+//! assert_eq!(archived.len(), 1000);
+//! ```
+use super::*;
+use core::{
+    convert::TryFrom,
+    fmt,
+    marker::PhantomData,
+    num::*,
+};
+
+/// The current version of the archive format.
+///
+/// Archives written with a different version are rejected.
+pub const VERSION: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"SMAR";
+const HEADER_SIZE: usize = 20;
+const BITMAP_SIZE: usize = MAX / 8;
+
+/// Types with a fixed-size encoding that can be stored in an archive.
+pub trait Archive: Sized
+{
+    /// The size of the encoding in bytes
+    const SIZE: usize;
+
+    /// Write the encoding of this value into `output`, which is exactly `SIZE` bytes long.
+    fn write(&self, output: &mut [u8]);
+
+    /// Read a value from `input`, which is exactly `SIZE` bytes long.
+    ///
+    /// Returns `None` if `input` is not a valid encoding.
+    fn read(input: &[u8]) -> Option<Self>;
+}
+
+macro_rules! archive_int {
+    ($($ty:ty),*) => {
+	$(
+	    impl Archive for $ty
+	    {
+		const SIZE: usize = core::mem::size_of::<$ty>();
+
+		#[inline] fn write(&self, output: &mut [u8])
+		{
+		    output.copy_from_slice(&self.to_le_bytes())
+		}
+		#[inline] fn read(input: &[u8]) -> Option<Self>
+		{
+		    <[u8; core::mem::size_of::<$ty>()]>::try_from(input).ok().map(<$ty>::from_le_bytes)
+		}
+	    }
+	)*
+    };
+    ($($ty:ty: $nz:ty),*) => {
+	$(
+	    impl Archive for $nz
+	    {
+		const SIZE: usize = <$ty as Archive>::SIZE;
+
+		#[inline] fn write(&self, output: &mut [u8])
+		{
+		    self.get().write(output)
+		}
+		#[inline] fn read(input: &[u8]) -> Option<Self>
+		{
+		    <$ty>::read(input).and_then(<$nz>::new)
+		}
+	    }
+	)*
+    };
+}
+
+archive_int!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128);
+archive_int!(u8: NonZeroU8, i8: NonZeroI8, u16: NonZeroU16, i16: NonZeroI16, u32: NonZeroU32, i32: NonZeroI32, u64: NonZeroU64, i64: NonZeroI64, u128: NonZeroU128, i128: NonZeroI128);
+
+impl Archive for ()
+{
+    const SIZE: usize = 0;
+
+    #[inline] fn write(&self, _: &mut [u8]) {}
+    #[inline] fn read(_: &[u8]) -> Option<Self>
+    {
+	Some(())
+    }
+}
+
+impl Archive for bool
+{
+    const SIZE: usize = 1;
+
+    #[inline] fn write(&self, output: &mut [u8])
+    {
+	output[0] = *self as u8;
+    }
+    #[inline] fn read(input: &[u8]) -> Option<Self>
+    {
+	match input {
+	    [0] => Some(false),
+	    [1] => Some(true),
+	    _ => None,
+	}
+    }
+}
+
+impl Archive for char
+{
+    const SIZE: usize = 4;
+
+    #[inline] fn write(&self, output: &mut [u8])
+    {
+	u32::from(*self).write(output)
+    }
+    #[inline] fn read(input: &[u8]) -> Option<Self>
+    {
+	u32::read(input).and_then(char::from_u32)
+    }
+}
+
+impl<T: Archive, const N: usize> Archive for [T; N]
+{
+    const SIZE: usize = T::SIZE * N;
+
+    // Elements are indexed rather than `chunks()`ed, since there are no chunks of zero-sized elements to iterate over
+    fn write(&self, output: &mut [u8])
+    {
+	for (i, x) in self.iter().enumerate() {
+	    x.write(&mut output[i * T::SIZE..][..T::SIZE]);
+	}
+    }
+    fn read(input: &[u8]) -> Option<Self>
+    {
+	if input.len() != Self::SIZE {
+	    return None;
+	}
+	let mut items = (0..N).map(|i| T::read(&input[i * T::SIZE..][..T::SIZE]));
+	let mut failed = false;
+	let array = core::array::from_fn(|_| match items.next().flatten() {
+	    Some(x) => Some(x),
+	    None => {
+		failed = true;
+		None
+	    },
+	});
+	if failed {
+	    None
+	} else {
+	    Some(array.map(Option::unwrap))
+	}
+    }
+}
+
+/// An error validating an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArchiveError
+{
+    /// The buffer is too short for the header or for the number of pages it declares, or too long.
+    BadLength,
+    /// The buffer does not start with the archive magic bytes.
+    BadMagic,
+    /// The archive was written with an unsupported format version.
+    UnsupportedVersion(u8),
+    /// The key or value size in the archive does not match the sizes of the requested types.
+    SizeMismatch,
+    /// The archive has no pages.
+    NoPages,
+    /// The entry in this slot of this page does not decode to a valid key and value.
+    InvalidEntry {
+	/// The index of the page containing the entry
+	page: usize,
+	/// The slot the entry is in
+	slot: u8,
+    },
+    /// The entry in this slot of this page has a key that does not collapse to the slot's index.
+    Misplaced {
+	/// The index of the page containing the entry
+	page: usize,
+	/// The slot the entry is in
+	slot: u8,
+    },
+}
+
+impl fmt::Display for ArchiveError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::BadLength => write!(f, "archive has an invalid length"),
+	    Self::BadMagic => write!(f, "bad magic bytes"),
+	    Self::UnsupportedVersion(v) => write!(f, "unsupported archive version {} (expected {})", v, VERSION),
+	    Self::SizeMismatch => write!(f, "archive key or value size does not match the requested types"),
+	    Self::NoPages => write!(f, "archive has no pages"),
+	    Self::InvalidEntry { page, slot } => write!(f, "entry in slot {} of page {} is invalid", slot, page),
+	    Self::Misplaced { page, slot } => write!(f, "entry in slot {} of page {} has a key that does not collapse to its slot", slot, page),
+	}
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ArchiveError{}
+
+#[inline] const fn stride<K: Archive, V: Archive>() -> usize
+{
+    K::SIZE + V::SIZE
+}
+
+#[inline] const fn page_size<K: Archive, V: Archive>() -> usize
+{
+    BITMAP_SIZE + MAX * stride::<K,V>()
+}
+
+/// The number of bytes the archive of `map` takes up.
+pub fn archived_len<K, V>(map: &Map<K,V>) -> usize
+where K: Collapse + Archive,
+      V: Archive
+{
+    HEADER_SIZE + map.num_pages() * page_size::<K,V>()
+}
+
+
+/// Write the archive of `map` to the start of `output`, returning the number of bytes written.
+///
+/// # Panics
+/// If `output` is shorter than [`archived_len(map)`](archived_len).
+pub fn write<K, V>(map: &Map<K,V>, output: &mut [u8]) -> usize
+where K: Collapse + Archive,
+      V: Archive
+{
+    let len = archived_len(map);
+    let output = &mut output[..len];
+    output.iter_mut().for_each(|x| *x = 0);
+
+    let (header, pages) = output.split_at_mut(HEADER_SIZE);
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = VERSION;
+    (K::SIZE as u32).write(&mut header[8..12]);
+    (V::SIZE as u32).write(&mut header[12..16]);
+    (map.num_pages() as u32).write(&mut header[16..20]);
+
+    for (page, output) in map.0.iter().zip(pages.chunks_exact_mut(page_size::<K,V>()))
+    {
+	let (bitmap, slots) = output.split_at_mut(BITMAP_SIZE);
+	for (slot, kv) in page.0.iter().enumerate()
+	{
+	    if let Some((key, value)) = kv {
+		bitmap[slot / 8] |= 1 << (slot % 8);
+		let (k, v) = slots[slot * stride::<K,V>()..][..stride::<K,V>()].split_at_mut(K::SIZE);
+		key.write(k);
+		value.write(v);
+	    }
+	}
+    }
+    len
+}
+
+/// Write the archive of `map` to a new `Vec`.
+pub fn to_vec<K, V>(map: &Map<K,V>) -> Vec<u8>
+where K: Collapse + Archive,
+      V: Archive
+{
+    let mut output = vec![0; archived_len(map)];
+    write(map, &mut output[..]);
+    output
+}
+
+/// A read-only `Map` backed by an archive in a byte buffer.
+///
+/// The buffer is validated once when this is created, and lookups then read entries directly from it without copying or allocating anything except the returned key and value.
+/// Since keys and values are decoded rather than referenced, the buffer needs no particular alignment.
+///
+/// Unlike [`Map::validate()`](Map::validate), the validation does not check for keys that are present in more than one page. If there are any (e.g. the archive was not written by [`write()`], or was written from a map that was itself invalid), lookups return the first matching entry.
+pub struct ArchivedMap<'a, K, V>
+{
+    bytes: &'a [u8],
+    pages: usize,
+    _phantom: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'a, K, V> Clone for ArchivedMap<'a, K, V>
+{
+    #[inline] fn clone(&self) -> Self
+    {
+	*self
+    }
+}
+impl<'a, K, V> Copy for ArchivedMap<'a, K, V>{}
+
+impl<'a, K, V> fmt::Debug for ArchivedMap<'a, K, V>
+where K: Collapse + Archive + fmt::Debug,
+      V: Archive + fmt::Debug
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, K, V> ArchivedMap<'a, K, V>
+where K: Collapse + Archive,
+      V: Archive
+{
+    /// Validate an archive and create a map that reads from it.
+    ///
+    /// Every occupied slot is checked to contain a valid key and value, and a key that collapses to that slot.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ArchiveError>
+    {
+	let header = bytes.get(..HEADER_SIZE).ok_or(ArchiveError::BadLength)?;
+	if &header[..4] != MAGIC {
+	    return Err(ArchiveError::BadMagic);
+	}
+	if header[4] != VERSION {
+	    return Err(ArchiveError::UnsupportedVersion(header[4]));
+	}
+	let field = |range: core::ops::Range<usize>| u32::read(&header[range]).map_or(usize::MAX, |x| x as usize);
+	if field(8..12) != K::SIZE || field(12..16) != V::SIZE {
+	    return Err(ArchiveError::SizeMismatch);
+	}
+	let pages = field(16..20);
+	if pages == 0 {
+	    return Err(ArchiveError::NoPages);
+	}
+	if pages.checked_mul(page_size::<K,V>()).and_then(|x| x.checked_add(HEADER_SIZE)) != Some(bytes.len()) {
+	    return Err(ArchiveError::BadLength);
+	}
+
+	let map = Self {
+	    bytes,
+	    pages,
+	    _phantom: PhantomData,
+	};
+	for page in 0..pages
+	{
+	    for slot in 0..=u8::MAX
+	    {
+		if map.is_occupied(page, slot) {
+		    match map.entry(page, slot) {
+			Some((key, _)) if key.collapse() != slot => return Err(ArchiveError::Misplaced { page, slot }),
+			Some(_) => (),
+			None => return Err(ArchiveError::InvalidEntry { page, slot }),
+		    }
+		}
+	    }
+	}
+	Ok(map)
+    }
+
+    /// The bytes of the archive
+    #[inline] pub fn as_bytes(&self) -> &'a [u8]
+    {
+	self.bytes
+    }
+
+    /// The number of pages in the archive
+    #[inline] pub fn num_pages(&self) -> usize
+    {
+	self.pages
+    }
+
+    /// The number of entries in the archive
+    pub fn len(&self) -> usize
+    {
+	(0..self.pages).map(|page| self.bitmap(page).iter().map(|x| x.count_ones() as usize).sum::<usize>()).sum()
+    }
+
+    /// Are there no entries in the archive
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	(0..self.pages).all(|page| self.bitmap(page).iter().all(|&x| x == 0))
+    }
+
+    #[inline] fn page(&self, page: usize) -> &'a [u8]
+    {
+	&self.bytes[HEADER_SIZE + page * page_size::<K,V>()..][..page_size::<K,V>()]
+    }
+
+    #[inline] fn bitmap(&self, page: usize) -> &'a [u8]
+    {
+	&self.page(page)[..BITMAP_SIZE]
+    }
+
+    #[inline] fn is_occupied(&self, page: usize, slot: u8) -> bool
+    {
+	let slot = usize::from(slot);
+	self.bitmap(page)[slot / 8] & (1 << (slot % 8)) != 0
+    }
+
+    #[inline] fn slot(&self, page: usize, slot: u8) -> (&'a [u8], &'a [u8])
+    {
+	self.page(page)[BITMAP_SIZE + usize::from(slot) * stride::<K,V>()..][..stride::<K,V>()].split_at(K::SIZE)
+    }
+
+    /// Decode the entry in an occupied slot
+    #[inline] fn entry(&self, page: usize, slot: u8) -> Option<(K, V)>
+    {
+	let (key, value) = self.slot(page, slot);
+	Some((K::read(key)?, V::read(value)?))
+    }
+
+    /// Find the page containing `key`
+    fn find(&self, key: &K) -> Option<(usize, u8)>
+    where K: Eq
+    {
+	let slot = key.collapse();
+	(0..self.pages)
+	    .filter(|&page| self.is_occupied(page, slot))
+	    .find(|&page| K::read(self.slot(page, slot).0).as_ref() == Some(key))
+	    .map(|page| (page, slot))
+    }
+
+    /// Get the value of this key
+    pub fn get(&self, key: &K) -> Option<V>
+    where K: Eq
+    {
+	let (page, slot) = self.find(key)?;
+	V::read(self.slot(page, slot).1)
+    }
+
+    /// Is this key present in the archive
+    #[inline] pub fn contains_key(&self, key: &K) -> bool
+    where K: Eq
+    {
+	self.find(key).is_some()
+    }
+
+    /// An iterator over the entries in the archive, in the same order as the archived `Map`'s.
+    #[inline] pub fn iter(&self) -> Iter<'a, K, V>
+    {
+	Iter {
+	    map: *self,
+	    page: 0,
+	    slot: 0,
+	}
+    }
+
+    /// An iterator over the keys in the archive
+    #[inline] pub fn keys(&self) -> impl Iterator<Item = K> + 'a
+    where K: 'a, V: 'a
+    {
+	self.iter().map(|(k, _)| k)
+    }
+
+    /// An iterator over the values in the archive
+    #[inline] pub fn values(&self) -> impl Iterator<Item = V> + 'a
+    where K: 'a, V: 'a
+    {
+	self.iter().map(|(_, v)| v)
+    }
+
+    /// Decode the whole archive into a `Map`.
+    pub fn to_map(&self) -> Map<K,V>
+    where K: Eq
+    {
+	let mut map = Map::with_capacity(self.pages);
+	map.extend(self.iter());
+	map
+    }
+}
+
+impl<'a, K, V> IntoIterator for ArchivedMap<'a, K, V>
+where K: Collapse + Archive,
+      V: Archive
+{
+    type Item = (K, V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+
+/// An iterator over the entries of an [`ArchivedMap`].
+pub struct Iter<'a, K, V>
+{
+    map: ArchivedMap<'a, K, V>,
+    page: usize,
+    slot: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+where K: Collapse + Archive,
+      V: Archive
+{
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+	while self.page < self.map.pages {
+	    while self.slot < MAX {
+		let slot = self.slot as u8;
+		self.slot += 1;
+		if self.map.is_occupied(self.page, slot) {
+		    if let Some(kv) = self.map.entry(self.page, slot) {
+			return Some(kv);
+		    }
+		}
+	    }
+	    self.page += 1;
+	    self.slot = 0;
+	}
+	None
+    }
+}
+
+impl<'a, K, V> core::iter::FusedIterator for Iter<'a, K, V>
+where K: Collapse + Archive,
+      V: Archive{}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn round_trip()
+    {
+	let map: Map<u16, [u8; 3]> = (0..700u16).map(|x| (x, [x as u8, (x >> 8) as u8, 7])).collect();
+	let bytes = to_vec(&map);
+	assert_eq!(bytes.len(), archived_len(&map));
+
+	let archived = ArchivedMap::<u16, [u8; 3]>::new(&bytes[..]).expect("invalid archive");
+	assert_eq!(archived.num_pages(), map.num_pages());
+	assert_eq!(archived.len(), 700);
+	for x in 0..700u16 {
+	    assert_eq!(archived.get(&x), map.get(&x).copied());
+	}
+	assert!(!archived.contains_key(&700));
+	assert!(archived.iter().eq(map.iter().copied()));
+	assert_eq!(archived.to_map(), map);
+    }
+
+    #[test]
+    fn zero_sized()
+    {
+	let mut bytes = [0u8; 2];
+	[(); 3].write(&mut []);
+	assert_eq!(<[(); 3]>::read(&[]), Some([(); 3]));
+	assert_eq!(<[(); 0]>::read(&[]), Some([]));
+	assert_eq!(<[(); 3]>::read(&bytes[..1]), None);
+
+	[[(); 2], [(); 2]].write(&mut []);
+	assert_eq!(<[[(); 2]; 2]>::read(&[]), Some([[(); 2]; 2]));
+	[5u8, 6].write(&mut bytes);
+	assert_eq!(<[u8; 2]>::read(&bytes), Some([5, 6]));
+
+	let map: Map<u8, [(); 4]> = (0..10).map(|x| (x, [(); 4])).collect();
+	let bytes = to_vec(&map);
+	let archived = ArchivedMap::<u8, [(); 4]>::new(&bytes[..]).expect("invalid archive");
+	assert_eq!(archived.get(&3), Some([(); 4]));
+	assert_eq!(archived.to_map(), map);
+    }
+
+    #[test]
+    fn empty()
+    {
+	let map: Map<char, bool> = Map::new();
+	let bytes = to_vec(&map);
+	let archived = ArchivedMap::<char, bool>::new(&bytes[..]).expect("invalid archive");
+	assert!(archived.is_empty());
+	assert_eq!(archived.get(&'a'), None);
+	assert_eq!(format!("{:?}", archived), "{}");
+    }
+
+    #[test]
+    fn validation()
+    {
+	let map = smallmap!{ {1u8 => true}, {2u8 => false} };
+	let bytes = to_vec(&map);
+	let check = |bytes: &[u8]| ArchivedMap::<u8, bool>::new(bytes).map(|_| ());
+
+	assert_eq!(check(&bytes[..]), Ok(()));
+	assert_eq!(check(&bytes[..bytes.len() - 1]), Err(ArchiveError::BadLength));
+	assert_eq!(check(&bytes[..10]), Err(ArchiveError::BadLength));
+	assert_eq!(ArchivedMap::<u16, bool>::new(&bytes[..]).map(|_| ()), Err(ArchiveError::SizeMismatch));
+
+	let mut bad = bytes.clone();
+	bad[0] = b'X';
+	assert_eq!(check(&bad[..]), Err(ArchiveError::BadMagic));
+
+	let mut bad = bytes.clone();
+	bad[4] = VERSION + 1;
+	assert_eq!(check(&bad[..]), Err(ArchiveError::UnsupportedVersion(VERSION + 1)));
+
+	// The value of slot 1 is not a valid `bool`
+	let slot = HEADER_SIZE + BITMAP_SIZE + 2;
+	let mut bad = bytes.clone();
+	bad[slot + 1] = 2;
+	assert_eq!(check(&bad[..]), Err(ArchiveError::InvalidEntry { page: 0, slot: 1 }));
+
+	// The key of slot 1 collapses to slot 3
+	let mut bad = bytes.clone();
+	bad[slot] = 3;
+	assert_eq!(check(&bad[..]), Err(ArchiveError::Misplaced { page: 0, slot: 1 }));
+    }
+}
@@ -0,0 +1,325 @@
+//! A bidirectional map, built on two `Map`s.
+//!
+//! Every left value is paired with exactly one right value and vice versa, so either side can be looked up in constant time.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! # use smallmap::bimap::Overwritten;
+//! let mut map = BiMap::new();
+//! map.insert('a', 1u8);
+//! map.insert('b', 2u8);
+//! assert_eq!(map.get_by_left(&'a'), Some(&1));
+//! assert_eq!(map.get_by_right(&2), Some(&'b'));
+//!
+//! // Pairing 'a' with 2 breaks up both ('a', 1) and ('b', 2)
+//! assert_eq!(map.insert('a', 2), Overwritten::Both(('a', 1), ('b', 2)));
+//! assert_eq!(map.len(), 1);
+//! ```
+use super::*;
+use core::fmt::{self, Debug,};
+
+/// The pairs evicted from a `BiMap` by `BiMap::insert()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Overwritten<L, R>
+{
+    /// Neither value was in the map
+    Neither,
+    /// The left value was paired with a different right value, this pair was removed
+    Left(L, R),
+    /// The right value was paired with a different left value, this pair was removed
+    Right(L, R),
+    /// This exact pair was already in the map
+    Pair(L, R),
+    /// Both values were paired with different values, the pair of the left value and the pair of the right value were removed
+    Both((L, R), (L, R)),
+}
+
+impl<L, R> Overwritten<L, R>
+{
+    /// Did the insertion remove any existing pairs
+    #[inline] pub fn did_overwrite(&self) -> bool
+    {
+	!matches!(self, Self::Neither)
+    }
+}
+
+/// A one-to-one map between left values of type `L` and right values of type `R`.
+///
+/// Both values of each pair are stored in both internal maps, so `L` and `R` must be `Clone`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct BiMap<L, R>
+{
+    left: Map<L, R>,
+    right: Map<R, L>,
+}
+
+impl<L, R> BiMap<L, R>
+{
+    /// The map of left values to right values
+    #[inline] pub fn left_map(&self) -> &Map<L, R>
+    {
+	&self.left
+    }
+
+    /// The map of right values to left values
+    #[inline] pub fn right_map(&self) -> &Map<R, L>
+    {
+	&self.right
+    }
+
+    /// Consume this map into its map of left values to right values
+    #[inline] pub fn into_left_map(self) -> Map<L, R>
+    {
+	self.left
+    }
+
+    /// Consume this map into its map of right values to left values
+    #[inline] pub fn into_right_map(self) -> Map<R, L>
+    {
+	self.right
+    }
+}
+
+impl<L, R> BiMap<L, R>
+where L: Collapse,
+      R: Collapse
+{
+    /// Create a new empty `BiMap`
+    #[inline] pub fn new() -> Self
+    {
+	Self {
+	    left: Map::new(),
+	    right: Map::new(),
+	}
+    }
+
+    /// The number of pairs in the map
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.left.len()
+    }
+
+    /// Is the map empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.left.is_empty()
+    }
+
+    /// The right value paired with this left value
+    #[inline] pub fn get_by_left<Q>(&self, left: &Q) -> Option<&R>
+    where L: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.left.get(left)
+    }
+
+    /// The left value paired with this right value
+    #[inline] pub fn get_by_right<Q>(&self, right: &Q) -> Option<&L>
+    where R: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.right.get(right)
+    }
+
+    /// Is this left value in the map
+    #[inline] pub fn contains_left<Q>(&self, left: &Q) -> bool
+    where L: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.left.contains_key(left)
+    }
+
+    /// Is this right value in the map
+    #[inline] pub fn contains_right<Q>(&self, right: &Q) -> bool
+    where R: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.right.contains_key(right)
+    }
+
+    /// Remove the pair with this left value, returning it if it was present.
+    pub fn remove_by_left<Q>(&mut self, left: &Q) -> Option<(L, R)>
+    where L: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (left, right) = self.left.remove_entry(left)?;
+	self.right.remove(&right);
+	Some((left, right))
+    }
+
+    /// Remove the pair with this right value, returning it if it was present.
+    pub fn remove_by_right<Q>(&mut self, right: &Q) -> Option<(L, R)>
+    where R: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (right, left) = self.right.remove_entry(right)?;
+	self.left.remove(&left);
+	Some((left, right))
+    }
+
+    /// Remove all pairs
+    #[inline] pub fn clear(&mut self)
+    {
+	self.left.clear();
+	self.right.clear();
+    }
+
+    /// An iterator over all pairs
+    #[inline] pub fn iter(&self) -> impl Iterator<Item = (&L, &R)>
+    {
+	self.left.iter().map(|(l, r)| (l, r))
+    }
+
+    /// An iterator over all left values
+    #[inline] pub fn left_values(&self) -> impl Iterator<Item = &L>
+    {
+	self.left.keys()
+    }
+
+    /// An iterator over all right values
+    #[inline] pub fn right_values(&self) -> impl Iterator<Item = &R>
+    {
+	self.right.keys()
+    }
+}
+
+impl<L, R> BiMap<L, R>
+where L: Collapse + Clone,
+      R: Collapse + Clone
+{
+    /// Pair `left` with `right`, removing any existing pairs either of them was in.
+    ///
+    /// The returned value describes which pairs (if any) were removed.
+    pub fn insert(&mut self, left: L, right: R) -> Overwritten<L, R>
+    {
+	let by_left = self.remove_by_left(&left);
+	let by_right = self.remove_by_right(&right);
+	let overwritten = match (by_left, by_right) {
+	    (None, None) => Overwritten::Neither,
+	    (Some(pair), None) if pair.1 == right => Overwritten::Pair(pair.0, pair.1),
+	    (Some(pair), None) => Overwritten::Left(pair.0, pair.1),
+	    (None, Some(pair)) => Overwritten::Right(pair.0, pair.1),
+	    (Some(l), Some(r)) => Overwritten::Both(l, r),
+	};
+	self.left.insert(left.clone(), right.clone());
+	self.right.insert(right, left);
+	overwritten
+    }
+
+    /// Pair `left` with `right` only if neither is already in the map.
+    ///
+    /// # Returns
+    /// The pair back if either value was present.
+    pub fn insert_no_overwrite(&mut self, left: L, right: R) -> Result<(), (L, R)>
+    {
+	if self.contains_left(&left) || self.contains_right(&right) {
+	    return Err((left, right));
+	}
+	self.left.insert(left.clone(), right.clone());
+	self.right.insert(right, left);
+	Ok(())
+    }
+}
+
+impl<L: Collapse, R: Collapse> Default for BiMap<L, R>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<L: Debug, R: Debug> Debug for BiMap<L, R>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	self.left.fmt(f)
+    }
+}
+
+impl<L, R> core::iter::FromIterator<(L, R)> for BiMap<L, R>
+where L: Collapse + Clone,
+      R: Collapse + Clone
+{
+    /// Later pairs overwrite earlier pairs they conflict with.
+    fn from_iter<I: IntoIterator<Item=(L, R)>>(iter: I) -> Self
+    {
+	let mut this = Self::new();
+	this.extend(iter);
+	this
+    }
+}
+
+impl<L, R> core::iter::Extend<(L, R)> for BiMap<L, R>
+where L: Collapse + Clone,
+      R: Collapse + Clone
+{
+    fn extend<I: IntoIterator<Item = (L, R)>>(&mut self, iter: I)
+    {
+	for (left, right) in iter.into_iter()
+	{
+	    self.insert(left, right);
+	}
+    }
+}
+
+impl<L: Collapse, R> IntoIterator for BiMap<L, R>
+{
+    type Item = (L, R);
+    type IntoIter = iter::IntoIter<L, R>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.left.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn consistent<L: Collapse + Debug, R: Collapse + Debug>(map: &BiMap<L, R>)
+    {
+	assert_eq!(map.left.len(), map.right.len());
+	for (l, r) in map.iter() {
+	    assert_eq!(map.get_by_right(r), Some(l));
+	}
+    }
+
+    #[test]
+    fn insert()
+    {
+	let mut map = BiMap::new();
+	assert_eq!(map.insert("one", 1), Overwritten::Neither);
+	assert_eq!(map.insert("two", 2), Overwritten::Neither);
+	assert_eq!(map.insert("one", 1), Overwritten::Pair("one", 1));
+	assert_eq!(map.insert("one", 3), Overwritten::Left("one", 1));
+	assert_eq!(map.insert("three", 3), Overwritten::Right("one", 3));
+	assert_eq!(map.insert("three", 2), Overwritten::Both(("three", 3), ("two", 2)));
+	assert_eq!(map.len(), 1);
+	consistent(&map);
+
+	assert_eq!(map.insert_no_overwrite("four", 2), Err(("four", 2)));
+	assert_eq!(map.insert_no_overwrite("four", 4), Ok(()));
+	consistent(&map);
+    }
+
+    #[test]
+    fn remove()
+    {
+	let mut map: BiMap<u16, char> = (0..1000).map(|i| (i, char::from_u32(0x100 + i as u32).unwrap())).collect();
+	assert_eq!(map.len(), 1000);
+	consistent(&map);
+	assert_eq!(map.remove_by_left(&10), Some((10, '\u{10a}')));
+	assert_eq!(map.remove_by_right(&'\u{10b}'), Some((11, '\u{10b}')));
+	assert_eq!(map.remove_by_left(&10), None);
+	assert!(!map.contains_right(&'\u{10a}'));
+	assert!(!map.contains_left(&11));
+	assert_eq!(map.len(), 998);
+	consistent(&map);
+    }
+}
@@ -0,0 +1,246 @@
+//! `smallmap-analyze`: Check how well a set of keys collapses into a `Map`.
+//!
+//! Reads keys from a file or stdin, one per line, and builds a `Map` from them with each of the built-in collapse strategies, printing how they are laid out.
+//!
+//! # Usage
+//! ```text
+//! smallmap-analyze [--format lines|hex|int] [FILE]
+//! ```
+use smallmap::{
+    Collapse,
+    Map,
+    Primitive,
+    Stats,
+};
+use std::{
+    env,
+    fmt,
+    fs,
+    io::{self, Read},
+    process,
+};
+
+/// How each line of input is parsed into a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format
+{
+    /// Each line is a string key
+    Lines,
+    /// Each line is a byte string key written in hex
+    Hex,
+    /// Each line is an integer key
+    Int,
+}
+
+impl Format
+{
+    fn parse(from: &str) -> Option<Self>
+    {
+	Some(match from {
+	    "lines" => Self::Lines,
+	    "hex" => Self::Hex,
+	    "int" => Self::Int,
+	    _ => return None,
+	})
+    }
+}
+
+#[derive(Debug)]
+enum Error
+{
+    Usage(String),
+    Io(String, io::Error),
+    Parse(usize, String),
+}
+
+impl fmt::Display for Error
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::Usage(msg) => write!(f, "{}", msg),
+	    Self::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+	    Self::Parse(line, msg) => write!(f, "line {}: {}", line, msg),
+	}
+    }
+}
+
+/// Byte string keys that use the XOR fold of [`smallmap::collapse()`] directly instead of going through `Hash`.
+#[derive(Debug, PartialEq, Eq)]
+struct Folded(Vec<u8>);
+
+impl Collapse for Folded
+{
+    #[inline] fn collapse(&self) -> u8 {
+	smallmap::collapse(&self.0)
+    }
+}
+
+/// String keys that use the XOR fold of [`smallmap::collapse_iter()`] over their chars truncated to bytes.
+#[derive(Debug, PartialEq, Eq)]
+struct FoldedChars(String);
+
+impl Collapse for FoldedChars
+{
+    #[inline] fn collapse(&self) -> u8 {
+	smallmap::collapse_iter(self.0.chars().map(|c| c as u8))
+    }
+}
+
+/// Build a `Map` from these keys and return its statistics.
+fn analyze<K: Collapse>(keys: impl IntoIterator<Item = K>) -> Stats
+{
+    keys.into_iter().map(|k| (k, ())).collect::<Map<K, ()>>().stats()
+}
+
+/// Parse a line of hex digits (ignoring whitespace and an optional `0x` prefix) into bytes.
+fn parse_hex(line: &str) -> Result<Vec<u8>, String>
+{
+    let line: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    let digits = line.strip_prefix("0x").or_else(|| line.strip_prefix("0X")).unwrap_or(&line[..]);
+    if let Some(c) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+	return Err(format!("invalid hex digit {:?} in {:?}", c, digits));
+    }
+    if digits.len() & 1 != 0 {
+	return Err(format!("odd number of hex digits in {:?}", digits));
+    }
+    // All digits are ASCII, so each pair of bytes is a valid `str` and byte
+    Ok(digits.as_bytes().chunks(2)
+	.map(|pair| u8::from_str_radix(std::str::from_utf8(pair).expect("hex digits are ASCII"), 16).expect("validated hex digits"))
+	.collect())
+}
+
+fn run(format: Format, input: &str) -> Result<Vec<(&'static str, Stats)>, Error>
+{
+    // Lines are numbered before blank ones are skipped, so that errors point at the right line
+    let lines = input.lines().zip(1..).filter(|(line, _)| !line.trim().is_empty());
+    Ok(match format {
+	Format::Lines => vec![
+	    ("Collapse (String)", analyze(lines.clone().map(|(x, _)| String::from(x)))),
+	    ("collapse (XOR fold of bytes)", analyze(lines.clone().map(|(x, _)| Folded(x.as_bytes().to_vec())))),
+	    ("collapse_iter (XOR fold of chars)", analyze(lines.map(|(x, _)| FoldedChars(x.to_owned())))),
+	],
+	Format::Hex => {
+	    let keys = lines
+		.map(|(line, i)| parse_hex(line).map_err(|e| Error::Parse(i, e)))
+		.collect::<Result<Vec<_>, _>>()?;
+	    vec![
+		("Collapse (Vec<u8>)", analyze(keys.iter().cloned())),
+		("collapse (XOR fold of bytes)", analyze(keys.into_iter().map(Folded))),
+	    ]
+	},
+	Format::Int => {
+	    let keys = lines
+		.map(|(line, i)| line.trim().parse::<i64>().map_err(|e| Error::Parse(i, format!("{:?}: {}", line, e))))
+		.collect::<Result<Vec<_>, _>>()?;
+	    vec![
+		("Collapse (i64)", analyze(keys.iter().copied())),
+		("Primitive<i64>", analyze(keys.iter().copied().map(Primitive::new))),
+		("collapse (XOR fold of LE bytes)", analyze(keys.into_iter().map(|x| Folded(x.to_le_bytes().to_vec())))),
+	    ]
+	},
+    })
+}
+
+fn print_stats(name: &str, stats: &Stats)
+{
+    println!("{}", name);
+    println!("  keys:          {}", stats.len());
+    println!("  pages:         {}", stats.pages());
+    println!("  longest chain: {}", stats.longest_chain());
+    println!("  fill ratio:    {:.3}", stats.fill_ratio());
+    for (i, ratio) in stats.fill_ratios().enumerate() {
+	println!("    page {:<4}     {:.3} ({} slots)", i, ratio, stats.page_fill()[i]);
+    }
+    println!("  slot depths:");
+    for (depth, count) in stats.depth_histogram().into_iter().enumerate().filter(|&(_, n)| n > 0) {
+	println!("    {:<4} x{}", depth, count);
+    }
+    println!("  memory:        {} bytes allocated, {} bytes in empty slots (with `()` values)", stats.allocated_bytes(), stats.wasted_bytes());
+}
+
+fn usage() -> String
+{
+    format!("Usage: {} [--format lines|hex|int] [FILE]\n\nReads keys one per line from FILE (or stdin if not given or `-`) and prints how they are laid out in a `Map` by each collapse strategy.", env::args().next().unwrap_or_else(|| "smallmap-analyze".to_owned()))
+}
+
+fn main()
+{
+    fn inner() -> Result<(), Error>
+    {
+	let mut format = Format::Lines;
+	let mut path = None;
+
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+	    match &arg[..] {
+		"-h" | "--help" => {
+		    println!("{}", usage());
+		    return Ok(());
+		},
+		"-f" | "--format" => {
+		    let value = args.next().ok_or_else(|| Error::Usage(usage()))?;
+		    format = Format::parse(&value).ok_or_else(|| Error::Usage(format!("unknown format {:?}\n\n{}", value, usage())))?;
+		},
+		_ if path.is_none() => path = Some(arg),
+		_ => return Err(Error::Usage(usage())),
+	    }
+	}
+
+	let input = match path.as_deref() {
+	    None | Some("-") => {
+		let mut input = String::new();
+		io::stdin().read_to_string(&mut input).map_err(|e| Error::Io("stdin".to_owned(), e))?;
+		input
+	    },
+	    Some(path) => fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?,
+	};
+
+	for (i, (name, stats)) in run(format, &input)?.iter().enumerate() {
+	    if i > 0 {
+		println!();
+	    }
+	    print_stats(name, stats);
+	}
+	Ok(())
+    }
+
+    if let Err(err) = inner() {
+	eprintln!("error: {}", err);
+	process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn hex()
+    {
+	assert_eq!(parse_hex("00ff10"), Ok(vec![0x00, 0xff, 0x10]));
+	assert_eq!(parse_hex("0xAb cd"), Ok(vec![0xab, 0xcd]));
+	assert_eq!(parse_hex("0XAB"), Ok(vec![0xab]));
+	assert_eq!(parse_hex(""), Ok(vec![]));
+	assert!(parse_hex("abc").is_err());
+	assert!(parse_hex("aé1").is_err());
+	assert!(parse_hex("é1").is_err());
+	assert!(parse_hex("+f").is_err());
+	assert!(parse_hex("0x0x").is_err());
+    }
+
+    #[test]
+    fn run_hex()
+    {
+	assert!(matches!(run(Format::Hex, "01\naé1\n"), Err(Error::Parse(2, _))));
+	assert!(matches!(run(Format::Hex, "01\n\nzz"), Err(Error::Parse(3, _))));
+	assert!(matches!(run(Format::Int, "1\n\n\nabc"), Err(Error::Parse(4, _))));
+	let stats = run(Format::Int, "1\n  \n\t\n2").expect("blank lines are skipped");
+	assert!(stats.iter().all(|(_, stats)| stats.len() == 2));
+	let stats = run(Format::Hex, "01\n \n").expect("blank lines are skipped");
+	assert!(stats.iter().all(|(_, stats)| stats.len() == 1));
+	let stats = run(Format::Hex, "0x01\n0X0201\n").expect("valid input");
+	assert!(stats.iter().all(|(_, stats)| stats.len() == 2));
+    }
+}
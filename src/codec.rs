@@ -0,0 +1,757 @@
+//! A compact binary encoding for `Map`s that does not need `serde`.
+//!
+//! Types are written with [`Encode`] and read back with [`Decode`], through a [`Writer`] and [`Reader`].
+//! Slices and `Vec<u8>` can be used as writers and readers without `std`; with the `std` feature, [`IoWriter`] and [`IoReader`] adapt `std::io::Write` and `std::io::Read`.
+//!
+//! # Format
+//! * Integers are written in little-endian byte order, regardless of the platform's.
+//! * `usize`, `isize` and all lengths are written as 64 bit values (lengths as unsigned LEB128 variable-length integers.)
+//! * `bool` is a single `0` or `1` byte, `char` is its `u32` value.
+//! * Byte slices and strings are their length followed by their bytes.
+//! * A `Map` is the magic bytes `SMAP`, a format [`VERSION`] byte, the number of entries, then each key followed by its value.
+//! * A set of single-byte keys (see [`ByteKey`]) is the magic bytes `SBST`, a format [`VERSION`] byte, then a 256-bit bitmap of the values present.
+//!
+//! Decoding rejects truncated or malformed input with a [`DecodeError`].
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let map = smallmap!{ {"one".to_owned() => 1u32}, {"two".to_owned() => 2u32} };
+//! let bytes = codec::to_vec(&map).unwrap();
+//! let map2: Map<String, u32> = codec::from_slice(&bytes).unwrap();
+//! assert_eq!(map2.get("two"), Some(&2));
+//! ```
+use super::*;
+use alloc::string::String;
+use core::{
+    convert::TryFrom,
+    fmt,
+    num::*,
+};
+
+/// The current version of the `Map` and byte set encodings.
+///
+/// Data written with a different version is rejected when decoding.
+pub const VERSION: u8 = 1;
+
+const MAP_MAGIC: &[u8; 4] = b"SMAP";
+const BYTE_SET_MAGIC: &[u8; 4] = b"SBST";
+
+/// The maximum number of bytes read into a buffer at once when decoding variable-length data, so a corrupt length cannot cause a huge allocation.
+const CHUNK: usize = 4096;
+
+/// An error encoding a value.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EncodeError
+{
+    /// The output buffer is too small for the encoded value.
+    BufferFull,
+    /// The underlying writer failed.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for EncodeError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::BufferFull => write!(f, "output buffer is full"),
+	    #[cfg(feature = "std")]
+	    Self::Io(err) => write!(f, "i/o error: {}", err),
+	}
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EncodeError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+	match self {
+	    Self::Io(err) => Some(err),
+	    _ => None,
+	}
+    }
+}
+
+/// An error decoding a value.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum DecodeError
+{
+    /// The input ended before the value was complete.
+    UnexpectedEof,
+    /// The input has bytes left over after the value.
+    TrailingBytes,
+    /// The input does not start with the expected magic bytes.
+    BadMagic,
+    /// The input was written with an unsupported format version.
+    UnsupportedVersion(u8),
+    /// A variable-length integer is too long or does not fit in the target type.
+    InvalidLength,
+    /// A `bool` is neither `0` nor `1`.
+    InvalidBool(u8),
+    /// A `char` is not a valid unicode scalar value.
+    InvalidChar(u32),
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// A non-zero integer is zero.
+    InvalidNonZero,
+    /// A key appears in a map or set more than once.
+    DuplicateKey,
+    /// An enum has an invalid tag byte.
+    InvalidTag(u8),
+    /// The underlying reader failed.
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl fmt::Display for DecodeError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::UnexpectedEof => write!(f, "unexpected end of input"),
+	    Self::TrailingBytes => write!(f, "trailing bytes after value"),
+	    Self::BadMagic => write!(f, "bad magic bytes"),
+	    Self::UnsupportedVersion(v) => write!(f, "unsupported format version {} (expected {})", v, VERSION),
+	    Self::InvalidLength => write!(f, "invalid length"),
+	    Self::InvalidBool(b) => write!(f, "invalid bool value {}", b),
+	    Self::InvalidChar(c) => write!(f, "invalid char value {:#x}", c),
+	    Self::InvalidUtf8 => write!(f, "invalid utf-8 in string"),
+	    Self::InvalidNonZero => write!(f, "zero value for non-zero integer"),
+	    Self::DuplicateKey => write!(f, "duplicate key"),
+	    Self::InvalidTag(t) => write!(f, "invalid enum tag {}", t),
+	    #[cfg(feature = "std")]
+	    Self::Io(err) => write!(f, "i/o error: {}", err),
+	}
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+    {
+	match self {
+	    Self::Io(err) => Some(err),
+	    _ => None,
+	}
+    }
+}
+
+/// A sink that encoded bytes are written to.
+pub trait Writer
+{
+    /// Write all of `bytes`.
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), EncodeError>;
+}
+
+/// A source that encoded bytes are read from.
+pub trait Reader
+{
+    /// Fill all of `buffer`, failing with [`DecodeError::UnexpectedEof`] if there are not enough bytes.
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), DecodeError>;
+}
+
+impl<W: Writer + ?Sized> Writer for &mut W
+{
+    #[inline] fn write_all(&mut self, bytes: &[u8]) -> Result<(), EncodeError>
+    {
+	(**self).write_all(bytes)
+    }
+}
+
+impl<R: Reader + ?Sized> Reader for &mut R
+{
+    #[inline] fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), DecodeError>
+    {
+	(**self).read_exact(buffer)
+    }
+}
+
+impl Writer for Vec<u8>
+{
+    #[inline] fn write_all(&mut self, bytes: &[u8]) -> Result<(), EncodeError>
+    {
+	self.extend_from_slice(bytes);
+	Ok(())
+    }
+}
+
+/// Writing to a slice fills it from the start, advancing it past the written bytes.
+impl Writer for &mut [u8]
+{
+    fn write_all(&mut self, bytes: &[u8]) -> Result<(), EncodeError>
+    {
+	if bytes.len() > self.len() {
+	    return Err(EncodeError::BufferFull);
+	}
+	let (head, tail) = core::mem::take(self).split_at_mut(bytes.len());
+	head.copy_from_slice(bytes);
+	*self = tail;
+	Ok(())
+    }
+}
+
+/// Reading from a slice advances it past the read bytes.
+impl Reader for &[u8]
+{
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), DecodeError>
+    {
+	if buffer.len() > self.len() {
+	    return Err(DecodeError::UnexpectedEof);
+	}
+	let (head, tail) = self.split_at(buffer.len());
+	buffer.copy_from_slice(head);
+	*self = tail;
+	Ok(())
+    }
+}
+
+/// Adaptor for using a `std::io::Write` as a [`Writer`].
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoWriter<W>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Writer for IoWriter<W>
+{
+    #[inline] fn write_all(&mut self, bytes: &[u8]) -> Result<(), EncodeError>
+    {
+	self.0.write_all(bytes).map_err(EncodeError::Io)
+    }
+}
+
+/// Adaptor for using a `std::io::Read` as a [`Reader`].
+///
+/// Unlike [`from_slice()`], decoding from this reader does not check for trailing bytes after a value.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct IoReader<R>(pub R);
+
+#[cfg(feature = "std")]
+impl<R: std::io::Read> Reader for IoReader<R>
+{
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), DecodeError>
+    {
+	self.0.read_exact(buffer).map_err(|err| match err.kind() {
+	    std::io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof,
+	    _ => DecodeError::Io(err),
+	})
+    }
+}
+
+/// Types that can be written in this module's binary format.
+pub trait Encode
+{
+    /// Write this value to `writer`.
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>;
+}
+
+/// Types that can be read from this module's binary format.
+pub trait Decode: Sized
+{
+    /// Read a value from `reader`.
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>;
+}
+
+/// Encode `value` into a new `Vec<u8>`.
+pub fn to_vec<T: Encode + ?Sized>(value: &T) -> Result<Vec<u8>, EncodeError>
+{
+    let mut output = Vec::new();
+    value.encode(&mut output)?;
+    Ok(output)
+}
+
+/// Encode `value` into the start of `buffer`, returning the number of bytes written.
+pub fn to_slice<T: Encode + ?Sized>(value: &T, buffer: &mut [u8]) -> Result<usize, EncodeError>
+{
+    let len = buffer.len();
+    let mut rest = buffer;
+    value.encode(&mut rest)?;
+    Ok(len - rest.len())
+}
+
+/// Decode a value from `bytes`, which must contain exactly one encoded value.
+pub fn from_slice<T: Decode>(mut bytes: &[u8]) -> Result<T, DecodeError>
+{
+    let value = T::decode(&mut bytes)?;
+    if bytes.is_empty() {
+	Ok(value)
+    } else {
+	Err(DecodeError::TrailingBytes)
+    }
+}
+
+/// Encode `value` to a `std::io::Write`.
+#[cfg(feature = "std")]
+#[inline] pub fn to_writer<T: Encode + ?Sized, W: std::io::Write>(value: &T, writer: W) -> Result<(), EncodeError>
+{
+    value.encode(&mut IoWriter(writer))
+}
+
+/// Decode a value from a `std::io::Read`.
+#[cfg(feature = "std")]
+#[inline] pub fn from_reader<T: Decode, R: std::io::Read>(reader: R) -> Result<T, DecodeError>
+{
+    T::decode(&mut IoReader(reader))
+}
+
+/// Write an unsigned LEB128 variable-length integer
+pub(crate) fn write_varint<W: Writer + ?Sized>(writer: &mut W, mut value: u64) -> Result<(), EncodeError>
+{
+    let mut buffer = [0u8; 10];
+    let mut i = 0;
+    loop {
+	let byte = (value & 0x7f) as u8;
+	value >>= 7;
+	if value == 0 {
+	    buffer[i] = byte;
+	    break;
+	}
+	buffer[i] = byte | 0x80;
+	i += 1;
+    }
+    writer.write_all(&buffer[..=i])
+}
+
+/// Read an unsigned LEB128 variable-length integer
+pub(crate) fn read_varint<R: Reader + ?Sized>(reader: &mut R) -> Result<u64, DecodeError>
+{
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+	let byte = u8::decode(reader)?;
+	let bits = u64::from(byte & 0x7f);
+	if shift == 63 && bits > 1 {
+	    return Err(DecodeError::InvalidLength);
+	}
+	value |= bits << shift;
+	if byte & 0x80 == 0 {
+	    return Ok(value);
+	}
+    }
+    Err(DecodeError::InvalidLength)
+}
+
+#[inline] fn write_len<W: Writer + ?Sized>(writer: &mut W, len: usize) -> Result<(), EncodeError>
+{
+    write_varint(writer, len as u64)
+}
+
+#[inline] fn read_len<R: Reader + ?Sized>(reader: &mut R) -> Result<usize, DecodeError>
+{
+    usize::try_from(read_varint(reader)?).map_err(|_| DecodeError::InvalidLength)
+}
+
+/// Read `len` bytes, without trusting `len` enough to allocate it all up front.
+fn read_bytes<R: Reader + ?Sized>(reader: &mut R, len: usize) -> Result<Vec<u8>, DecodeError>
+{
+    let mut output = Vec::with_capacity(len.min(CHUNK));
+    while output.len() < len {
+	let start = output.len();
+	output.resize(start + (len - start).min(CHUNK), 0);
+	reader.read_exact(&mut output[start..])?;
+    }
+    Ok(output)
+}
+
+fn write_header<W: Writer + ?Sized>(writer: &mut W, magic: &[u8; 4]) -> Result<(), EncodeError>
+{
+    writer.write_all(magic)?;
+    writer.write_all(&[VERSION])
+}
+
+fn read_header<R: Reader + ?Sized>(reader: &mut R, magic: &[u8; 4]) -> Result<(), DecodeError>
+{
+    let mut buffer = [0u8; 4];
+    reader.read_exact(&mut buffer)?;
+    if &buffer != magic {
+	return Err(DecodeError::BadMagic);
+    }
+    match u8::decode(reader)? {
+	VERSION => Ok(()),
+	version => Err(DecodeError::UnsupportedVersion(version)),
+    }
+}
+
+macro_rules! int {
+    ($($ty:ty),*) => {
+	$(
+	    impl Encode for $ty
+	    {
+		#[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+		{
+		    writer.write_all(&self.to_le_bytes())
+		}
+	    }
+	    impl Decode for $ty
+	    {
+		#[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+		{
+		    let mut buffer = [0u8; core::mem::size_of::<$ty>()];
+		    reader.read_exact(&mut buffer)?;
+		    Ok(<$ty>::from_le_bytes(buffer))
+		}
+	    }
+	)*
+    };
+    ($($ty:ty: $nz:ty),*) => {
+	$(
+	    impl Encode for $nz
+	    {
+		#[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+		{
+		    self.get().encode(writer)
+		}
+	    }
+	    impl Decode for $nz
+	    {
+		#[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+		{
+		    <$nz>::new(<$ty>::decode(reader)?).ok_or(DecodeError::InvalidNonZero)
+		}
+	    }
+	)*
+    };
+}
+
+int!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128);
+int!(u8: NonZeroU8, i8: NonZeroI8, u16: NonZeroU16, i16: NonZeroI16, u32: NonZeroU32, i32: NonZeroI32, u64: NonZeroU64, i64: NonZeroI64, u128: NonZeroU128, i128: NonZeroI128);
+
+impl Encode for usize
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	(*self as u64).encode(writer)
+    }
+}
+impl Decode for usize
+{
+    #[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	usize::try_from(u64::decode(reader)?).map_err(|_| DecodeError::InvalidLength)
+    }
+}
+
+impl Encode for isize
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	(*self as i64).encode(writer)
+    }
+}
+impl Decode for isize
+{
+    #[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	isize::try_from(i64::decode(reader)?).map_err(|_| DecodeError::InvalidLength)
+    }
+}
+
+impl Encode for ()
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, _: &mut W) -> Result<(), EncodeError>
+    {
+	Ok(())
+    }
+}
+impl Decode for ()
+{
+    #[inline] fn decode<R: Reader + ?Sized>(_: &mut R) -> Result<Self, DecodeError>
+    {
+	Ok(())
+    }
+}
+
+impl Encode for bool
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	(*self as u8).encode(writer)
+    }
+}
+impl Decode for bool
+{
+    #[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	match u8::decode(reader)? {
+	    0 => Ok(false),
+	    1 => Ok(true),
+	    b => Err(DecodeError::InvalidBool(b)),
+	}
+    }
+}
+
+impl Encode for char
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	u32::from(*self).encode(writer)
+    }
+}
+impl Decode for char
+{
+    #[inline] fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	let value = u32::decode(reader)?;
+	char::from_u32(value).ok_or(DecodeError::InvalidChar(value))
+    }
+}
+
+impl Encode for [u8]
+{
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	write_len(writer, self.len())?;
+	writer.write_all(self)
+    }
+}
+impl Encode for Vec<u8>
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	self[..].encode(writer)
+    }
+}
+impl Decode for Vec<u8>
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	let len = read_len(reader)?;
+	read_bytes(reader, len)
+    }
+}
+
+impl Encode for str
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	self.as_bytes().encode(writer)
+    }
+}
+impl Encode for String
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	self.as_bytes().encode(writer)
+    }
+}
+impl Decode for String
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	String::from_utf8(Vec::decode(reader)?).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T
+{
+    #[inline] fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	(**self).encode(writer)
+    }
+}
+
+impl<K, V> Encode for Map<K,V>
+where K: Collapse + Encode,
+      V: Encode
+{
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	write_header(writer, MAP_MAGIC)?;
+	write_len(writer, self.len())?;
+	for (k, v) in self.iter() {
+	    k.encode(writer)?;
+	    v.encode(writer)?;
+	}
+	Ok(())
+    }
+}
+
+impl<K, V> Decode for Map<K,V>
+where K: Collapse + Decode,
+      V: Decode
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	read_header(reader, MAP_MAGIC)?;
+	let len = read_len(reader)?;
+	let mut map = Map::new();
+	for _ in 0..len {
+	    let key = K::decode(reader)?;
+	    let value = V::decode(reader)?;
+	    if map.insert(key, value).is_some() {
+		return Err(DecodeError::DuplicateKey);
+	    }
+	}
+	Ok(map)
+    }
+}
+
+/// Single-byte key types that a `Set` of can be encoded as a bitmap.
+pub trait ByteKey: Collapse + Copy + private::Sealed
+{
+    /// The byte representation of this key
+    fn to_byte(self) -> u8;
+    /// Create a key from its byte representation, if it is valid
+    fn from_byte(byte: u8) -> Option<Self>;
+}
+
+macro_rules! byte_key {
+    ($($ty:ty => |$b:ident| $from:expr, |$s:ident| $to:expr;)*) => {
+	$(
+	    impl ByteKey for $ty
+	    {
+		#[inline] fn to_byte(self) -> u8 {
+		    let $s = self;
+		    $to
+		}
+		#[inline] fn from_byte($b: u8) -> Option<Self> {
+		    $from
+		}
+	    }
+	)*
+    };
+}
+
+byte_key! {
+    u8 => |b| Some(b), |s| s;
+    i8 => |b| Some(b as i8), |s| s as u8;
+    NonZeroU8 => |b| NonZeroU8::new(b), |s| s.get();
+    NonZeroI8 => |b| NonZeroI8::new(b as i8), |s| s.get() as u8;
+}
+
+impl<T: ByteKey> Encode for Set<T>
+{
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	let mut bitmap = [0u8; MAX / 8];
+	for &value in self.iter() {
+	    let byte = value.to_byte();
+	    bitmap[usize::from(byte / 8)] |= 1 << (byte % 8);
+	}
+	write_header(writer, BYTE_SET_MAGIC)?;
+	writer.write_all(&bitmap)
+    }
+}
+
+impl<T: ByteKey> Decode for Set<T>
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	read_header(reader, BYTE_SET_MAGIC)?;
+	let mut bitmap = [0u8; MAX / 8];
+	reader.read_exact(&mut bitmap)?;
+	let mut set = Set::new();
+	for byte in (0..=u8::MAX).filter(|&b| bitmap[usize::from(b / 8)] & (1 << (b % 8)) != 0) {
+	    set.insert(T::from_byte(byte).ok_or(DecodeError::InvalidNonZero)?);
+	}
+	Ok(set)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn primitives()
+    {
+	assert_eq!(to_vec(&0x0102u16).unwrap(), [2, 1]);
+	assert_eq!(to_vec(&-1i32).unwrap(), [0xff; 4]);
+	assert_eq!(to_vec("hi").unwrap(), [2, b'h', b'i']);
+	assert_eq!(from_slice::<u64>(&to_vec(&u64::MAX).unwrap()).unwrap(), u64::MAX);
+	assert_eq!(from_slice::<char>(&to_vec(&'ł').unwrap()).unwrap(), 'ł');
+	assert_eq!(from_slice::<String>(&to_vec(&"x".repeat(300)).unwrap()).unwrap(), "x".repeat(300));
+
+	let mut buffer = [0u8; 16];
+	assert_eq!(to_slice(&1u32, &mut buffer[..]).unwrap(), 4);
+	assert!(matches!(to_slice(&1u128, &mut buffer[..1]), Err(EncodeError::BufferFull)));
+    }
+
+    #[test]
+    fn varint()
+    {
+	for &value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX].iter() {
+	    let mut output = Vec::new();
+	    write_varint(&mut output, value).unwrap();
+	    assert_eq!(read_varint(&mut &output[..]).unwrap(), value);
+	}
+	assert!(matches!(read_varint(&mut &[0xff; 11][..]), Err(DecodeError::InvalidLength)));
+	assert!(matches!(read_varint(&mut &[0x80][..]), Err(DecodeError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn map()
+    {
+	let map: Map<u16, String> = (0..600).map(|x| (x, x.to_string())).collect();
+	let bytes = to_vec(&map).unwrap();
+	assert_eq!(&bytes[..5], b"SMAP\x01");
+
+	let map2: Map<u16, String> = from_slice(&bytes).unwrap();
+	assert_eq!(map2.len(), map.len());
+	assert!(map.iter().all(|(k, v)| map2.get(k) == Some(v)));
+
+	#[cfg(feature = "std")]
+	{
+	    let mut output = Vec::new();
+	    to_writer(&map, &mut output).unwrap();
+	    assert_eq!(output, bytes);
+	    let map3: Map<u16, String> = from_reader(&output[..]).unwrap();
+	    assert_eq!(map3, map2);
+	}
+    }
+
+    #[test]
+    fn malformed()
+    {
+	let map = smallmap!{ {1u8 => 'a'}, {2u8 => 'b'} };
+	let bytes = to_vec(&map).unwrap();
+
+	for len in 0..bytes.len() {
+	    assert!(matches!(from_slice::<Map<u8, char>>(&bytes[..len]), Err(DecodeError::UnexpectedEof)));
+	}
+	let mut extra = bytes.clone();
+	extra.push(0);
+	assert!(matches!(from_slice::<Map<u8, char>>(&extra), Err(DecodeError::TrailingBytes)));
+
+	let mut bad = bytes.clone();
+	bad[0] = b'X';
+	assert!(matches!(from_slice::<Map<u8, char>>(&bad), Err(DecodeError::BadMagic)));
+
+	let mut bad = bytes.clone();
+	bad[4] = 2;
+	assert!(matches!(from_slice::<Map<u8, char>>(&bad), Err(DecodeError::UnsupportedVersion(2))));
+
+	let mut bad = bytes.clone();
+	bad[7..11].copy_from_slice(&0xd800u32.to_le_bytes());
+	assert!(matches!(from_slice::<Map<u8, char>>(&bad), Err(DecodeError::InvalidChar(0xd800))));
+
+	let mut bad = bytes.clone();
+	bad[11] = 1;
+	assert!(matches!(from_slice::<Map<u8, char>>(&bad), Err(DecodeError::DuplicateKey)));
+
+	// A huge length with no data must fail without allocating it
+	let mut bad = b"SMAP\x01\x01\x01".to_vec();
+	write_varint(&mut bad, u64::MAX >> 1).unwrap();
+	assert!(matches!(from_slice::<Map<u8, Vec<u8>>>(&bad), Err(DecodeError::InvalidLength) | Err(DecodeError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn byte_set()
+    {
+	let set: space::NonZeroByteSet = (1..=255).step_by(7).filter_map(NonZeroU8::new).collect();
+	let bytes = to_vec(&set).unwrap();
+	assert_eq!(bytes.len(), 5 + 32);
+	let set2: space::NonZeroByteSet = from_slice(&bytes).unwrap();
+	assert_eq!(set, set2);
+
+	let mut zero = bytes.clone();
+	zero[5] |= 1;
+	assert!(matches!(from_slice::<space::NonZeroByteSet>(&zero), Err(DecodeError::InvalidNonZero)));
+    }
+}
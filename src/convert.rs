@@ -0,0 +1,206 @@
+//! Conversions between `Map`/`Set` and other collections
+//!
+//! Since a `Map` can be compared with a `HashMap` or `BTreeMap` as well as with another `Map`, comparing it with a value whose type is inferred (such as the result of deserialising) needs that type to be annotated.
+use super::*;
+use alloc::collections::BTreeMap;
+#[cfg(feature = "std")]
+use std::{
+    collections::HashMap,
+    hash::BuildHasher,
+};
+
+impl<K: Collapse, V, const N: usize> From<[(K, V); N]> for Map<K, V>
+{
+    #[inline] fn from(entries: [(K, V); N]) -> Self
+    {
+	IntoIterator::into_iter(entries).collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Collapse, V, S> From<HashMap<K, V, S>> for Map<K, V>
+{
+    #[inline] fn from(map: HashMap<K, V, S>) -> Self
+    {
+	map.into_iter().collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> From<Map<K, V>> for HashMap<K, V, S>
+where K: Collapse + Hash,
+      S: BuildHasher + Default
+{
+    #[inline] fn from(map: Map<K, V>) -> Self
+    {
+	map.into_iter().collect()
+    }
+}
+
+impl<K: Collapse, V> From<BTreeMap<K, V>> for Map<K, V>
+{
+    #[inline] fn from(map: BTreeMap<K, V>) -> Self
+    {
+	map.into_iter().collect()
+    }
+}
+
+impl<K, V> From<Map<K, V>> for BTreeMap<K, V>
+where K: Collapse + Ord
+{
+    #[inline] fn from(map: Map<K, V>) -> Self
+    {
+	map.into_iter().collect()
+    }
+}
+
+impl<'a, K, V> core::iter::Extend<(&'a K, &'a V)> for Map<K, V>
+where K: Collapse + Copy,
+      V: Copy
+{
+    fn extend<I: IntoIterator<Item = (&'a K, &'a V)>>(&mut self, iter: I)
+    {
+	self.extend(iter.into_iter().map(|(&k, &v)| (k, v)))
+    }
+}
+
+impl<'a, T: Collapse + Copy> core::iter::FromIterator<&'a T> for Set<T>
+{
+    /// Useful for collecting the keys of a `Map` into a `Set`.
+    #[inline] fn from_iter<I: IntoIterator<Item = &'a T>>(iter: I) -> Self
+    {
+	iter.into_iter().copied().collect()
+    }
+}
+
+impl<'a, T: Collapse + Copy> core::iter::Extend<&'a T> for Set<T>
+{
+    #[inline] fn extend<I: IntoIterator<Item = &'a T>>(&mut self, iter: I)
+    {
+	self.extend(iter.into_iter().copied())
+    }
+}
+
+/// Does `map` have exactly the entries that `get` finds, given that the other map has `len` entries.
+#[inline] fn eq_by<'a, K, V, F>(map: &'a Map<K, V>, len: usize, mut get: F) -> bool
+where K: Collapse,
+      V: PartialEq,
+      F: FnMut(&'a K) -> Option<&'a V>
+{
+    map.len() == len && map.iter().all(|(k, v)| get(k) == Some(v))
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> PartialEq<HashMap<K, V, S>> for Map<K, V>
+where K: Collapse + Hash,
+      V: PartialEq,
+      S: BuildHasher
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &HashMap<K, V, S>) -> bool
+    {
+	eq_by(self, other.len(), |k| other.get(k))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, S> PartialEq<Map<K, V>> for HashMap<K, V, S>
+where K: Collapse + Hash,
+      V: PartialEq,
+      S: BuildHasher
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &Map<K, V>) -> bool
+    {
+	other == self
+    }
+}
+
+impl<K, V> PartialEq<BTreeMap<K, V>> for Map<K, V>
+where K: Collapse + Ord,
+      V: PartialEq
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &BTreeMap<K, V>) -> bool
+    {
+	eq_by(self, other.len(), |k| other.get(k))
+    }
+}
+
+impl<K, V> PartialEq<Map<K, V>> for BTreeMap<K, V>
+where K: Collapse + Ord,
+      V: PartialEq
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &Map<K, V>) -> bool
+    {
+	other == self
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn std_maps()
+    {
+	let map = Map::from([('a', 1), ('b', 2), ('c', 3)]);
+	let hash: HashMap<_, _> = map.clone().into();
+	let btree: BTreeMap<_, _> = map.clone().into();
+	assert_eq!(map, hash);
+	assert_eq!(hash, map);
+	assert_eq!(map, btree);
+	assert_eq!(btree, map);
+	assert_eq!(Map::from(hash.clone()), map);
+	assert_eq!(Map::from(btree.clone()), map);
+
+	let mut other = map.clone();
+	other.insert('c', 4);
+	assert_ne!(other, hash);
+	assert_ne!(btree, other);
+	other.remove(&'c');
+	assert_ne!(other, hash);
+	assert_ne!(btree, other);
+    }
+
+    #[test]
+    fn layout_independent()
+    {
+	let a = Map::from([(1u16, 'a'), (257, 'b')]);
+	let mut b = Map::from([(257u16, 'b'), (1, 'a')]);
+	let btree: BTreeMap<_, _> = a.clone().into();
+	assert_eq!(a, btree);
+	assert_eq!(b, btree);
+	assert_eq!(a, b);
+	b.insert(1, 'c');
+	assert_ne!(a, b);
+	b.remove(&1);
+	assert_ne!(a, b);
+	b.insert(1, 'a');
+	assert_eq!(a, b);
+
+	let hash = |map: &Map<u16, char>| {
+	    let mut state = std::collections::hash_map::DefaultHasher::new();
+	    map.hash(&mut state);
+	    state.finish()
+	};
+	assert_eq!(hash(&a), hash(&b));
+	assert_ne!(hash(&a), hash(&Map::from([(1, 'x'), (257, 'y')])));
+    }
+
+    #[test]
+    fn borrowed()
+    {
+	let source = Map::from([(1u8, 10u8), (2, 20)]);
+	let mut map = Map::new();
+	map.extend(source.iter().map(|(k, v)| (k, v)));
+	assert_eq!(map, source);
+
+	let keys: Set<u8> = source.keys().collect();
+	assert!(keys.contains(&1) && keys.contains(&2));
+	assert_eq!(keys.len(), 2);
+    }
+}
@@ -0,0 +1,333 @@
+//! A multiset type for counting things, built on `Map`.
+//!
+//! The API is similar to that of Python's `collections.Counter`, except that counts are never negative: subtracting from a count saturates at zero, and keys whose count reaches zero are removed.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! fn max_char(chars: &str) -> Option<(char, usize)>
+//! {
+//!     let counter: Counter<char> = chars.chars().collect();
+//!     counter.most_common(1).first().map(|&(&c, n)| (c, n))
+//! }
+//! assert_eq!(max_char("hello world"), Some(('l', 3)));
+//! ```
+use super::*;
+use core::{
+    fmt::{self, Debug,},
+    ops::{
+	Add,
+	BitAnd,
+	BitOr,
+	Index,
+	Sub,
+    },
+};
+
+/// A count of occurances of each key.
+///
+/// This is a thin wrapper over a `Map` of keys to their (always non-zero) counts.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct Counter<K>(Map<K, usize>);
+
+impl<K> Counter<K>
+{
+    /// A reference to the underlying `Map`
+    #[inline] pub fn as_map(&self) -> &Map<K, usize>
+    {
+	&self.0
+    }
+
+    /// Consume this counter into the underlying `Map`
+    #[inline] pub fn into_map(self) -> Map<K, usize>
+    {
+	self.0
+    }
+}
+
+impl<K> Counter<K>
+where K: Collapse
+{
+    /// Create a new empty `Counter`
+    #[inline] pub fn new() -> Self
+    {
+	Self(Map::new())
+    }
+
+    /// The number of distinct keys counted
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.0.len()
+    }
+
+    /// Are there no keys counted
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.0.is_empty()
+    }
+
+    /// The sum of all counts
+    #[inline] pub fn total(&self) -> usize
+    {
+	self.0.values().sum()
+    }
+
+    /// The count of this key, which is `0` if it has not been counted.
+    #[inline] pub fn get<Q>(&self, key: &Q) -> usize
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.get(key).copied().unwrap_or(0)
+    }
+
+    /// Count one more occurance of this key, returning its new count.
+    #[inline] pub fn add(&mut self, key: K) -> usize
+    {
+	self.add_n(key, 1)
+    }
+
+    /// Count `n` more occurances of this key, returning its new count.
+    ///
+    /// If `n` is zero and the key has not been counted, it is not inserted.
+    pub fn add_n(&mut self, key: K, n: usize) -> usize
+    {
+	if n == 0 {
+	    return self.get(&key);
+	}
+	let count = self.0.entry(key).or_insert(0);
+	*count += n;
+	*count
+    }
+
+    /// Count `n` fewer occurances of this key, returning its new count.
+    ///
+    /// The count saturates at zero, and the key is removed if it reaches it.
+    pub fn subtract<Q>(&mut self, key: &Q, n: usize) -> usize
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	match self.0.get_mut(key) {
+	    Some(count) if *count > n => {
+		*count -= n;
+		*count
+	    },
+	    Some(_) => {
+		self.0.remove(key);
+		0
+	    },
+	    None => 0,
+	}
+    }
+
+    /// Remove this key, returning its count if it had been counted.
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<usize>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.remove(key)
+    }
+
+    /// Remove all counts
+    #[inline] pub fn clear(&mut self)
+    {
+	self.0.clear()
+    }
+
+    /// An iterator over all keys and their counts
+    #[inline] pub fn iter(&self) -> impl Iterator<Item = (&K, usize)>
+    {
+	self.0.iter().map(|(k, n)| (k, *n))
+    }
+
+    /// An iterator over all counted keys
+    #[inline] pub fn keys(&self) -> impl Iterator<Item = &K>
+    {
+	self.0.keys()
+    }
+
+    /// The `n` keys with the highest counts, with their counts, from highest to lowest.
+    ///
+    /// Keys with equal counts are in the order they are iterated in. To get all keys, pass `self.len()` (or `usize::MAX`).
+    pub fn most_common(&self, n: usize) -> Vec<(&K, usize)>
+    {
+	let mut all: Vec<_> = self.iter().collect();
+	all.sort_by(|(_, a), (_, b)| b.cmp(a));
+	all.truncate(n);
+	all
+    }
+
+    /// An iterator over each key repeated as many times as its count
+    // `iter::repeat_n()` would raise the minimum supported Rust version to 1.82
+    #[allow(clippy::manual_repeat_n)]
+    pub fn elements(&self) -> impl Iterator<Item = &K>
+    {
+	self.iter().flat_map(|(k, n)| core::iter::repeat(k).take(n))
+    }
+
+    /// Combine the counts of `self` and `rhs` for every key in either, keeping only non-zero results.
+    fn combine<F>(&self, rhs: &Self, mut f: F) -> Self
+    where K: Clone,
+	  F: FnMut(usize, usize) -> usize
+    {
+	let keys = self.keys().chain(rhs.keys().filter(|k| !self.0.contains_key(k)));
+	let mut output = Self::new();
+	for key in keys
+	{
+	    output.add_n(key.clone(), f(self.get(key), rhs.get(key)));
+	}
+	output
+    }
+}
+
+impl<K: Collapse> Default for Counter<K>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K: Debug> Debug for Counter<K>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	self.0.fmt(f)
+    }
+}
+
+impl<K, Q> Index<&Q> for Counter<K>
+where K: Collapse + Borrow<Q>,
+      Q: ?Sized + Collapse + Eq
+{
+    type Output = usize;
+
+    /// The count of this key, which is `0` if it has not been counted.
+    fn index(&self, key: &Q) -> &Self::Output
+    {
+	self.0.get(key).unwrap_or(&0)
+    }
+}
+
+impl<K: Collapse> From<Map<K, usize>> for Counter<K>
+{
+    /// Create a counter from a map of counts. Keys with zero counts are removed.
+    fn from(mut map: Map<K, usize>) -> Self
+    {
+	map.retain(|_, n| *n > 0);
+	Self(map)
+    }
+}
+
+impl<K: Collapse> core::iter::FromIterator<K> for Counter<K>
+{
+    fn from_iter<I: IntoIterator<Item=K>>(iter: I) -> Self
+    {
+	let mut this = Self::new();
+	this.extend(iter);
+	this
+    }
+}
+
+impl<K: Collapse> core::iter::Extend<K> for Counter<K>
+{
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I)
+    {
+	for key in iter.into_iter()
+	{
+	    self.add(key);
+	}
+    }
+}
+
+impl<K: Collapse> IntoIterator for Counter<K>
+{
+    type Item = (K, usize);
+    type IntoIter = iter::IntoIter<K, usize>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.0.into_iter()
+    }
+}
+
+macro_rules! counter_op {
+    ($trait:ident, $fn:ident, $op:expr, $doc:literal) => {
+	impl<'a, 'b, K> $trait<&'b Counter<K>> for &'a Counter<K>
+	where K: Collapse + Clone
+	{
+	    type Output = Counter<K>;
+
+	    #[doc = $doc]
+	    fn $fn(self, rhs: &'b Counter<K>) -> Self::Output
+	    {
+		self.combine(rhs, $op)
+	    }
+	}
+    };
+}
+
+counter_op!(Add, add, |a: usize, b: usize| a + b, "Returns a new `Counter` with the sum of the counts of `self` and `rhs`.");
+counter_op!(Sub, sub, usize::saturating_sub, "Returns a new `Counter` with the counts of `rhs` subtracted from those of `self`, keeping only positive counts.");
+counter_op!(BitOr, bitor, core::cmp::max, "Returns a new `Counter` with the maximum of the counts of `self` and `rhs`.");
+counter_op!(BitAnd, bitand, core::cmp::min, "Returns a new `Counter` with the minimum of the counts of `self` and `rhs`, keeping only keys counted in both.");
+
+#[cfg(test)]
+mod tests
+{
+    // Not `super::*`, since `ops::Add::add()` on `&Counter` would shadow `Counter::add()`.
+    use crate::*;
+
+    #[test]
+    fn counting()
+    {
+	let mut counter: Counter<char> = "abracadabra".chars().collect();
+	assert_eq!(counter.total(), 11);
+	assert_eq!(counter.len(), 5);
+	assert_eq!(counter[&'a'], 5);
+	assert_eq!(counter.get(&'z'), 0);
+	// 'b' and 'r' are both counted twice, and 'b' is in an earlier slot
+	assert_eq!(counter.most_common(2), vec![(&'a', 5), (&'b', 2)]);
+	assert_eq!(counter.most_common(1), vec![(&'a', 5)]);
+
+	assert_eq!(counter.add('z'), 1);
+	assert_eq!(counter.add_n('z', 2), 3);
+	assert_eq!(counter.add_n('y', 0), 0);
+	assert!(!counter.as_map().contains_key(&'y'));
+	assert_eq!(counter.subtract(&'z', 1), 2);
+	assert_eq!(counter.subtract(&'z', 5), 0);
+	assert!(!counter.as_map().contains_key(&'z'));
+	assert_eq!(counter.elements().filter(|&&c| c == 'b').count(), 2);
+    }
+
+    #[test]
+    fn arithmetic()
+    {
+	let a: Counter<char> = "aaab".chars().collect();
+	let b: Counter<char> = "abbc".chars().collect();
+	let check = |c: Counter<char>, expected: &[(char, usize)]| {
+	    assert_eq!(c.len(), expected.len());
+	    for &(k, n) in expected {
+		assert_eq!(c[&k], n);
+	    }
+	};
+	check(&a + &b, &[('a', 4), ('b', 3), ('c', 1)]);
+	check(&a - &b, &[('a', 2)]);
+	check(&a | &b, &[('a', 3), ('b', 2), ('c', 1)]);
+	check(&a & &b, &[('a', 1), ('b', 1)]);
+    }
+
+    #[test]
+    fn recount_after_removal()
+    {
+	// 1 and 257 collapse to the same slot, so 257 is counted on the second page
+	let mut counter = Counter::new();
+	counter.add(1u16);
+	counter.add(257);
+	counter.subtract(&1, 1);
+	assert!(!counter.is_empty());
+	assert_eq!(counter.add(257), 2);
+	assert_eq!((counter.get(&257), counter.total(), counter.len()), (2, 2, 1));
+    }
+}
@@ -0,0 +1,203 @@
+//! A cursor for walking and editing a `Map` in place
+//!
+//! A [`CursorMut`] points at one entry of a map, and moves between entries in the same order as [`Map::iter()`](Map::iter) (by page, then by slot). Since entries never move between slots, removing the current entry or inserting others does not disturb the cursor's position.
+//!
+//! The cursor holds the map itself and the index of its slot in the flattened pages, rather than being built on the map's iterators: [`PagesMut`] and [`PageElementsMut`] only move forward, and they would borrow the map so that it could not be inserted into while the cursor exists.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut map: Map<u8, u32> = (0..10).map(|i| (i, u32::from(i))).collect();
+//!
+//! let mut cursor = map.cursor_mut();
+//! while let Some((&key, value)) = cursor.current() {
+//!     if key % 2 == 0 {
+//!         cursor.remove_current();
+//!     } else {
+//!         *value *= 10;
+//!         cursor.move_next();
+//!     }
+//! }
+//! assert_eq!(map.len(), 5);
+//! assert_eq!(map[&3], 30);
+//! ```
+use super::*;
+
+/// A cursor over the entries of a `Map` that can mutate or remove them.
+///
+/// The cursor is either on an entry, or on a "ghost" position that is before the first entry and after the last one. Moving forward from the ghost position goes to the first entry, and moving backward goes to the last.
+///
+/// Created by [`Map::cursor_mut()`](Map::cursor_mut).
+pub struct CursorMut<'a, K, V>
+{
+    map: &'a mut Map<K, V>,
+    /// The index of the current slot in the flattened pages, `None` for the ghost position
+    index: Option<usize>,
+}
+
+impl<K, V> Map<K, V>
+{
+    /// A cursor positioned on the first entry of the map (or the ghost position, if the map is empty.)
+    pub fn cursor_mut(&mut self) -> CursorMut<'_, K, V>
+    {
+	let mut cursor = CursorMut {
+	    map: self,
+	    index: None,
+	};
+	cursor.move_next();
+	cursor
+    }
+}
+
+impl<'a, K, V> CursorMut<'a, K, V>
+{
+    #[inline] fn slot(&mut self, index: usize) -> &mut Option<(K, V)>
+    {
+	&mut self.map.0[index / MAX].0[index % MAX]
+    }
+
+    #[inline] fn occupied(&self, index: usize) -> bool
+    {
+	self.map.0[index / MAX].0[index % MAX].is_some()
+    }
+
+    /// The page and slot of the current entry, or `None` at the ghost position
+    #[inline] pub fn position(&self) -> Option<(usize, u8)>
+    {
+	self.index.map(|i| (i / MAX, (i % MAX) as u8))
+    }
+
+    /// The current entry, or `None` at the ghost position
+    #[inline] pub fn current(&mut self) -> Option<(&K, &mut V)>
+    {
+	let index = self.index?;
+	self.slot(index).as_mut().map(|(k, v)| (&*k, v))
+    }
+
+    /// Move to the next entry, or to the ghost position if this is the last entry.
+    ///
+    /// # Returns
+    /// If the cursor is now on an entry.
+    pub fn move_next(&mut self) -> bool
+    {
+	let start = self.index.map_or(0, |i| i + 1);
+	let end = self.map.0.len() * MAX;
+	self.index = (start..end).find(|&i| self.occupied(i));
+	self.index.is_some()
+    }
+
+    /// Move to the previous entry, or to the ghost position if this is the first entry.
+    ///
+    /// # Returns
+    /// If the cursor is now on an entry.
+    pub fn move_prev(&mut self) -> bool
+    {
+	let end = self.index.unwrap_or(self.map.0.len() * MAX);
+	self.index = (0..end).rev().find(|&i| self.occupied(i));
+	self.index.is_some()
+    }
+
+    /// Remove the current entry and move to the next one.
+    ///
+    /// # Returns
+    /// The removed entry, or `None` at the ghost position (in which case the cursor does not move.)
+    pub fn remove_current(&mut self) -> Option<(K, V)>
+    {
+	let index = self.index?;
+	let entry = self.slot(index).take();
+	self.move_next();
+	entry
+    }
+
+    /// Replace the value of the current entry, returning the old value.
+    ///
+    /// At the ghost position this does nothing and returns `None`.
+    #[inline] pub fn replace_current(&mut self, value: V) -> Option<V>
+    {
+	self.current().map(|(_, v)| core::mem::replace(v, value))
+    }
+
+    /// A shared reference to the map
+    #[inline] pub fn as_map(&self) -> &Map<K, V>
+    {
+	self.map
+    }
+}
+
+impl<'a, K, V> CursorMut<'a, K, V>
+where K: Collapse
+{
+    /// Move to the entry of this key, or to the ghost position if it is not in the map.
+    ///
+    /// # Returns
+    /// If the key was found.
+    pub fn seek<Q>(&mut self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let slot = usize::from(key.collapse());
+	self.index = self.map.0.iter().position(|page| matches!(page.search(key), Some((k, _)) if k.borrow() == key))
+	    .map(|page| page * MAX + slot);
+	self.index.is_some()
+    }
+
+    /// Insert an entry into the map without moving the cursor, returning the previous value of the key if it was present.
+    ///
+    /// The entry may be inserted before or after the cursor's position.
+    #[inline] pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	self.map.insert(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn movement()
+    {
+	let mut map: Map<u16, u16> = [1, 5, 257, 513].iter().map(|&i| (i, i)).collect();
+	let mut cursor = map.cursor_mut();
+	assert_eq!(cursor.position(), Some((0, 1)));
+	assert!(cursor.move_next());
+	assert!(cursor.move_next());
+	assert_eq!(cursor.position(), Some((1, 1)));
+	assert!(cursor.move_next());
+	assert_eq!(cursor.current().map(|(&k, _)| k), Some(513));
+	assert!(!cursor.move_next());
+	assert_eq!(cursor.current(), None);
+	assert!(cursor.move_prev());
+	assert_eq!(cursor.position(), Some((2, 1)));
+	while cursor.move_prev() {}
+	assert!(cursor.move_next());
+	assert_eq!(cursor.position(), Some((0, 1)));
+
+	assert!(cursor.seek(&257));
+	assert_eq!(cursor.replace_current(3), Some(257));
+	assert!(cursor.move_prev());
+	assert_eq!(cursor.current(), Some((&5, &mut 5)));
+	assert!(!cursor.seek(&2));
+	assert_eq!(cursor.replace_current(0), None);
+	assert_eq!(map[&257], 3);
+    }
+
+    #[test]
+    fn editing()
+    {
+	let mut map: Map<u16, u16> = (0..600).map(|i| (i, i)).collect();
+	let mut cursor = map.cursor_mut();
+	cursor.seek(&0);
+	cursor.remove_current();
+	cursor.seek(&255);
+	// Inserting behind the cursor (into the slot of 0) does not move it
+	assert_eq!(cursor.insert(768, 0), None);
+	assert_eq!(cursor.remove_current(), Some((255, 255)));
+	assert_eq!(cursor.position(), Some((1, 0)));
+	while cursor.remove_current().is_some() {}
+	assert_eq!(cursor.position(), None);
+	assert_eq!(map.len(), 255);
+	assert!(map.contains_key(&768));
+    }
+}
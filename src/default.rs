@@ -0,0 +1,280 @@
+//! A map that fills in missing values with defaults, built on `Map`.
+//!
+//! Indexing a `DefaultMap` never panics: `map[&key]` returns a shared default value for missing keys without inserting anything, and `map[&key]` in a mutable context inserts a fresh default first, like Python's `collections.defaultdict`.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut totals: DefaultMap<&str, u32> = DefaultMap::new();
+//! for (name, score) in [("alice", 3), ("bob", 5), ("alice", 4)] {
+//!     totals[&name] += score;
+//! }
+//! assert_eq!(totals[&"alice"], 7);
+//! assert_eq!(totals[&"carol"], 0);
+//! assert_eq!(totals.len(), 2);
+//! ```
+use super::*;
+use alloc::borrow::ToOwned;
+use core::{
+    fmt::{self, Debug,},
+    ops::{Index, IndexMut},
+};
+
+/// A `Map` that produces a default value for keys that are not in it.
+///
+/// New values are created by calling `F`. One value created by `F` when the map is constructed is kept to be returned by shared lookups of missing keys, so `F` should return the same value every time.
+#[derive(Clone)]
+pub struct DefaultMap<K, V, F = fn() -> V>
+{
+    map: Map<K, V>,
+    default: V,
+    factory: F,
+}
+
+impl<K, V, F> DefaultMap<K, V, F>
+{
+    /// A reference to the underlying `Map`
+    #[inline] pub fn as_map(&self) -> &Map<K, V>
+    {
+	&self.map
+    }
+
+    /// A mutable reference to the underlying `Map`
+    #[inline] pub fn as_map_mut(&mut self) -> &mut Map<K, V>
+    {
+	&mut self.map
+    }
+
+    /// Consume this map into the underlying `Map`
+    #[inline] pub fn into_map(self) -> Map<K, V>
+    {
+	self.map
+    }
+
+    /// The shared default value returned for missing keys
+    #[inline] pub fn default_value(&self) -> &V
+    {
+	&self.default
+    }
+}
+
+impl<K, V> DefaultMap<K, V>
+where K: Collapse,
+      V: Default
+{
+    /// Create a new empty `DefaultMap` whose default is `V::default()`
+    #[inline] pub fn new() -> Self
+    {
+	Self::from_map(Map::new())
+    }
+
+    /// Wrap a `Map`, using `V::default()` for missing keys
+    #[inline] pub fn from_map(map: Map<K, V>) -> Self
+    {
+	Self::from_map_with(map, V::default)
+    }
+}
+
+impl<K, V, F> DefaultMap<K, V, F>
+where K: Collapse,
+      F: FnMut() -> V
+{
+    /// Create a new empty `DefaultMap` that creates defaults with `factory`
+    #[inline] pub fn with_default(factory: F) -> Self
+    {
+	Self::from_map_with(Map::new(), factory)
+    }
+
+    /// Wrap a `Map`, creating defaults for missing keys with `factory`
+    pub fn from_map_with(map: Map<K, V>, mut factory: F) -> Self
+    {
+	Self {
+	    default: factory(),
+	    map,
+	    factory,
+	}
+    }
+
+    /// The number of entries in the map
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.map.len()
+    }
+
+    /// Is the map empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.map.is_empty()
+    }
+
+    /// The value of this key, or the shared default if it is not in the map.
+    #[inline] pub fn get<Q>(&self, key: &Q) -> &V
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.get(key).unwrap_or(&self.default)
+    }
+
+    /// A mutable reference to the value of this key, inserting a new default if it is not in the map.
+    #[inline] pub fn get_or_insert(&mut self, key: K) -> &mut V
+    {
+	let factory = &mut self.factory;
+	self.map.entry(key).or_insert_with(factory)
+    }
+
+    /// Does the map contain this key
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.contains_key(key)
+    }
+
+    /// Insert an entry, returning the previous value of the key if it was in the map
+    #[inline] pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	self.map.insert(key, value)
+    }
+
+    /// Remove this key, returning its value if it was in the map
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.remove(key)
+    }
+
+    /// Remove all entries
+    #[inline] pub fn clear(&mut self)
+    {
+	self.map.clear()
+    }
+
+    /// An iterator over all entries in the map
+    #[inline] pub fn iter(&self) -> Iter<'_, K, V>
+    {
+	self.map.iter()
+    }
+}
+
+impl<K: Collapse, V: Default> Default for DefaultMap<K, V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug, F> Debug for DefaultMap<K, V, F>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	self.map.fmt(f)
+    }
+}
+
+impl<K: Collapse, V: Default> From<Map<K, V>> for DefaultMap<K, V>
+{
+    #[inline] fn from(map: Map<K, V>) -> Self
+    {
+	Self::from_map(map)
+    }
+}
+
+impl<K, Q, V, F> Index<&Q> for DefaultMap<K, V, F>
+where K: Collapse + Borrow<Q>,
+      Q: ?Sized + Collapse + Eq,
+      F: FnMut() -> V
+{
+    type Output = V;
+
+    /// The value of this key, or the shared default if it is not in the map.
+    #[inline] fn index(&self, key: &Q) -> &Self::Output
+    {
+	self.get(key)
+    }
+}
+
+impl<K, Q, V, F> IndexMut<&Q> for DefaultMap<K, V, F>
+where K: Collapse + Borrow<Q>,
+      Q: ?Sized + Collapse + Eq + ToOwned<Owned = K>,
+      F: FnMut() -> V
+{
+    /// The value of this key, inserting a new default if it is not in the map.
+    fn index_mut(&mut self, key: &Q) -> &mut Self::Output
+    {
+	if self.map.contains_key(key) {
+	    self.map.get_mut(key).unwrap()
+	} else {
+	    self.get_or_insert(key.to_owned())
+	}
+    }
+}
+
+impl<K: Collapse, V: Default> core::iter::FromIterator<(K, V)> for DefaultMap<K, V>
+{
+    #[inline] fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self
+    {
+	Self::from_map(iter.into_iter().collect())
+    }
+}
+
+impl<K: Collapse, V, F> core::iter::Extend<(K, V)> for DefaultMap<K, V, F>
+{
+    #[inline] fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I)
+    {
+	self.map.extend(iter)
+    }
+}
+
+impl<K: Collapse, V, F> IntoIterator for DefaultMap<K, V, F>
+{
+    type Item = (K, V);
+    type IntoIter = iter::IntoIter<K, V>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.map.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn index()
+    {
+	let mut map: DefaultMap<String, Vec<u8>> = DefaultMap::new();
+	map["a"].push(1);
+	map["a"].push(2);
+	map["b"].push(3);
+	assert_eq!(map["a"], [1, 2]);
+	assert!(map["c"].is_empty());
+	assert!(!map.contains_key("c"));
+	assert_eq!(map.len(), 2);
+	map.get_or_insert(String::from("c"));
+	assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn factory()
+    {
+	let mut calls = 0;
+	let mut map = DefaultMap::with_default(|| {
+	    calls += 1;
+	    100
+	});
+	assert_eq!(map[&'x'], 100);
+	map[&'x'] -= 1;
+	map[&'x'] -= 1;
+	map[&'y'] += 1;
+	assert_eq!((map[&'x'], map[&'y'], *map.default_value()), (98, 101, 100));
+	drop(map);
+	assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,289 @@
+//! Comparing and merging `Map`s
+//!
+//! Since two `Map`s with the same key type collapse their keys to the same slots, they can be compared by walking their slots in lockstep.
+//! Only the (usually very few) entries in the same slot of each map are compared with each other, instead of looking up every key of one map in the other.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! use smallmap::diff::Change;
+//!
+//! let old = smallmap!{ {"a" => 1}, {"b" => 2}, {"c" => 3} };
+//! let new = smallmap!{ {"a" => 1}, {"b" => 20}, {"d" => 4} };
+//!
+//! let patch: Vec<_> = old.diff(&new).map(Change::cloned).collect();
+//! assert_eq!(patch.len(), 3);
+//! assert!(patch.contains(&Change::Changed("b", 2, 20)));
+//!
+//! let mut map = old.clone();
+//! map.apply(patch);
+//! assert_eq!(map, new);
+//! ```
+use super::*;
+
+/// A difference between two `Map`s.
+///
+/// [`Map::diff()`](Map::diff) produces changes of references to the maps' keys and values (`Change<&K, &V>`), and [`Map::apply()`](Map::apply) takes changes of owned keys and values (`Change<K, V>`.) See [`cloned()`](Change::cloned).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Change<K, V>
+{
+    /// This entry is only in the new map
+    Added(K, V),
+    /// This entry is only in the old map
+    Removed(K, V),
+    /// This key has the first value in the old map, and the second in the new map
+    Changed(K, V, V),
+}
+
+impl<K, V> Change<K, V>
+{
+    /// The key that this change applies to
+    #[inline] pub fn key(&self) -> &K
+    {
+	match self {
+	    Self::Added(k, _) | Self::Removed(k, _) | Self::Changed(k, _, _) => k,
+	}
+    }
+}
+
+impl<'a, K: Clone, V: Clone> Change<&'a K, &'a V>
+{
+    /// Clone the key and values of this change
+    pub fn cloned(self) -> Change<K, V>
+    {
+	match self {
+	    Self::Added(k, v) => Change::Added(k.clone(), v.clone()),
+	    Self::Removed(k, v) => Change::Removed(k.clone(), v.clone()),
+	    Self::Changed(k, old, new) => Change::Changed(k.clone(), old.clone(), new.clone()),
+	}
+    }
+}
+
+/// An iterator over the differences between two `Map`s.
+///
+/// See [`Map::diff()`](Map::diff).
+pub struct Diff<'a, K, V>
+{
+    old: &'a Map<K,V>,
+    new: &'a Map<K,V>,
+    slot: usize,
+    pending: Vec<Change<&'a K, &'a V>>,
+}
+
+impl<K,V> Map<K,V>
+{
+    /// All entries in this slot, across all pages
+    #[inline] fn column(&self, slot: usize) -> impl Iterator<Item = &(K, V)>
+    {
+	self.0.iter().filter_map(move |page| page.0[slot].as_ref())
+    }
+}
+
+impl<'a, K, V> Diff<'a, K, V>
+where K: Eq,
+      V: PartialEq
+{
+    /// Compare the next slot of both maps.
+    fn fill(&mut self)
+    {
+	let slot = self.slot;
+	self.slot += 1;
+
+	for (key, old) in self.old.column(slot)
+	{
+	    match self.new.column(slot).find(|(k, _)| k == key) {
+		Some((_, new)) if new != old => self.pending.push(Change::Changed(key, old, new)),
+		Some(_) => (),
+		None => self.pending.push(Change::Removed(key, old)),
+	    }
+	}
+	for (key, new) in self.new.column(slot)
+	{
+	    if !self.old.column(slot).any(|(k, _)| k == key) {
+		self.pending.push(Change::Added(key, new));
+	    }
+	}
+	self.pending.reverse();
+    }
+}
+
+impl<'a, K, V> Iterator for Diff<'a, K, V>
+where K: Eq,
+      V: PartialEq
+{
+    type Item = Change<&'a K, &'a V>;
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+	loop {
+	    if let Some(change) = self.pending.pop() {
+		return Some(change);
+	    }
+	    if self.slot >= MAX {
+		return None;
+	    }
+	    self.fill();
+	}
+    }
+}
+
+impl<'a, K: Eq, V: PartialEq> core::iter::FusedIterator for Diff<'a, K, V>{}
+
+impl<K,V> Map<K,V>
+where K: Collapse
+{
+    /// An iterator over the changes that turn this map into `other`.
+    ///
+    /// The changes are yielded in slot order.
+    #[inline] pub fn diff<'a>(&'a self, other: &'a Self) -> Diff<'a, K, V>
+    where V: PartialEq
+    {
+	Diff {
+	    old: self,
+	    new: other,
+	    slot: 0,
+	    pending: Vec::new(),
+	}
+    }
+
+    /// Apply changes produced by [`diff()`](Map::diff) to this map.
+    ///
+    /// Added and changed entries are inserted with their new values, and removed entries are removed. The old values in the changes are not checked against this map's values.
+    pub fn apply<I>(&mut self, changes: I)
+    where I: IntoIterator<Item = Change<K, V>>
+    {
+	for change in changes
+	{
+	    match change {
+		Change::Added(key, value) | Change::Changed(key, _, value) => {
+		    self.insert(key, value);
+		},
+		Change::Removed(key, _) => {
+		    self.remove(&key);
+		},
+	    }
+	}
+    }
+
+    /// Find the slot containing the entry for this key
+    fn slot_mut(&mut self, key: &K) -> Option<&mut Option<(K,V)>>
+    {
+	self.0.iter_mut().map(|page| page.search_mut(key)).find(|kv| matches!(kv, Some((k, _)) if k == key))
+    }
+
+    /// Move all entries from `other` into this map, calling `merge` to resolve the value of keys that are present in both.
+    ///
+    /// `merge` is called with the key, the value in this map, and the value in `other`, and returns the value to keep in this map.
+    ///
+    /// # Panics
+    /// If `merge` panics, both values it was given are dropped, so the entry for that key is no longer in this map. Entries of `other` that were not yet moved are dropped as well.
+    pub fn merge_with<F>(&mut self, other: Self, mut merge: F)
+    where F: FnMut(&K, V, V) -> V
+    {
+	for (key, value) in other
+	{
+	    match self.slot_mut(&key) {
+		Some(slot) => {
+		    if let Some((k, ours)) = slot.take() {
+			let value = merge(&k, ours, value);
+			*slot = Some((k, value));
+		    }
+		},
+		None => {
+		    self.insert(key, value);
+		},
+	    }
+	}
+    }
+
+    /// Merge `ours` and `theirs`, two maps that were both changed from this map, into a new map.
+    ///
+    /// For each key, if only one of `ours` or `theirs` changed it from this map (including adding or removing it), that change is kept. If both made the same change, it is kept as well.
+    /// Otherwise, `conflict` is called with the key and its values in this map, `ours` and `theirs` (`None` if it is not present in that map), and returns the value to keep, or `None` to leave the key out of the merged map.
+    pub fn merge3<F>(&self, ours: &Self, theirs: &Self, mut conflict: F) -> Self
+    where K: Clone,
+	  V: Clone + PartialEq,
+	  F: FnMut(&K, Option<&V>, Option<&V>, Option<&V>) -> Option<V>
+    {
+	let mut output = Self::with_capacity(ours.num_pages().max(theirs.num_pages()));
+	let keys = ours.keys().chain(theirs.keys().filter(|key| !ours.contains_key(key)));
+	for key in keys
+	{
+	    let (base, a, b) = (self.get(key), ours.get(key), theirs.get(key));
+	    let value = if a == b || b == base {
+		a.cloned()
+	    } else if a == base {
+		b.cloned()
+	    } else {
+		conflict(key, base, a, b)
+	    };
+	    if let Some(value) = value {
+		output.insert(key.clone(), value);
+	    }
+	}
+	output
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn diff_apply()
+    {
+	let old: Map<u16, u16> = (0..600).map(|x| (x, x)).collect();
+	let mut new = old.clone();
+	new.remove(&5);
+	new.remove(&300);
+	new.insert(7, 70);
+	new.insert(1000, 1);
+
+	let mut changes: Vec<_> = old.diff(&new).collect();
+	changes.sort_by_key(|change| *change.key());
+	assert_eq!(changes, vec![
+	    Change::Removed(&5, &5),
+	    Change::Changed(&7, &7, &70),
+	    Change::Removed(&300, &300),
+	    Change::Added(&1000, &1),
+	]);
+	assert_eq!(old.diff(&old).count(), 0);
+
+	let mut patched = old.clone();
+	patched.apply(changes.into_iter().map(Change::cloned));
+	assert_eq!(patched.diff(&new).count(), 0);
+	assert_eq!(patched.len(), new.len());
+    }
+
+    #[test]
+    fn merge_with()
+    {
+	let mut a = smallmap!{ {'a' => 1}, {'b' => 2} };
+	let b = smallmap!{ {'b' => 10}, {'c' => 3} };
+	a.merge_with(b, |_, x, y| x + y);
+	assert_eq!(a, smallmap!{ {'a' => 1}, {'b' => 12}, {'c' => 3} });
+
+	let b = smallmap!{ {'a' => 0} };
+	let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| a.merge_with(b, |_, _, _| panic!("merge failed"))));
+	assert!(result.is_err());
+	assert_eq!(a, smallmap!{ {'b' => 12}, {'c' => 3} });
+    }
+
+    #[test]
+    fn merge3()
+    {
+	let base = smallmap!{ {'a' => 1}, {'b' => 2}, {'c' => 3}, {'d' => 4} };
+	let ours = smallmap!{ {'a' => 10}, {'b' => 2}, {'c' => 30}, {'e' => 5} };
+	let theirs = smallmap!{ {'a' => 1}, {'c' => 31}, {'d' => 4}, {'e' => 5} };
+
+	let mut conflicts = Vec::new();
+	let merged = base.merge3(&ours, &theirs, |&k, base, ours, theirs| {
+	    conflicts.push(k);
+	    assert_eq!((base, ours, theirs), (Some(&3), Some(&30), Some(&31)));
+	    ours.copied()
+	});
+	assert_eq!(conflicts, vec!['c']);
+	assert_eq!(merged, smallmap!{ {'a' => 10}, {'c' => 30}, {'e' => 5} });
+    }
+}
@@ -0,0 +1,330 @@
+//! Generational handles to entries, built on `Map`.
+//!
+//! Entries in a `Map` stay in the same slot of the same page until they are removed, so a `SlotHandle` can find its entry again by position alone, without collapsing or comparing the key. Each slot also has a generation that is bumped whenever its entry is removed, so a handle to a removed entry never resolves to whatever entry is in that slot later.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut map = HandleMap::new();
+//! let (a, _) = map.insert("a", 1);
+//! *map.get_mut(a).unwrap() += 1;
+//! assert_eq!(map.get(a), Some(&2));
+//!
+//! assert_eq!(map.remove(a), Some(("a", 2)));
+//! let (b, _) = map.insert("a", 3); // Reuses the same slot
+//! assert_eq!(map.get(a), None);
+//! assert_eq!(map.get(b), Some(&3));
+//! ```
+use super::*;
+use core::{
+    fmt::{self, Debug,},
+    ops::Deref,
+};
+
+/// A handle to an entry in a `HandleMap`.
+///
+/// It stays valid until the entry is removed (replacing the value of the entry's key does not invalidate it).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SlotHandle
+{
+    page: usize,
+    slot: u8,
+    generation: u32,
+}
+
+impl SlotHandle
+{
+    /// The index of the page the entry is in
+    #[inline] pub fn page(&self) -> usize
+    {
+	self.page
+    }
+
+    /// The slot of the page the entry is in
+    #[inline] pub fn slot(&self) -> u8
+    {
+	self.slot
+    }
+
+    /// The generation of the slot when the handle was created
+    #[inline] pub fn generation(&self) -> u32
+    {
+	self.generation
+    }
+}
+
+/// A `Map` whose entries can be accessed through `SlotHandle`s.
+///
+/// The map can be read through `Deref`, but is only mutable through this type's methods so that slot generations stay correct. Pages are never removed from the map (so `Map::clean()` is not available), since that would move entries and invalidate their handles.
+///
+/// Generations are 32 bits and wrap, so a stale handle could only resolve to a different entry after its slot has been removed from 2<sup>32</sup> times.
+#[derive(Clone, PartialEq, Eq)]
+pub struct HandleMap<K, V>
+{
+    map: Map<K, V>,
+    generations: Vec<[u32; MAX]>,
+}
+
+impl<K, V> HandleMap<K, V>
+{
+    #[inline] fn handle_at(&self, page: usize, slot: u8) -> SlotHandle
+    {
+	SlotHandle {
+	    page,
+	    slot,
+	    generation: self.generations[page][usize::from(slot)],
+	}
+    }
+
+    /// The slot `handle` points to, if its generation matches
+    #[inline] fn slot(&self, handle: SlotHandle) -> Option<&Option<(K, V)>>
+    {
+	let current = *self.generations.get(handle.page)?.get(usize::from(handle.slot))?;
+	if current == handle.generation {
+	    self.map.0.get(handle.page).map(|page| &page.0[usize::from(handle.slot)])
+	} else {
+	    None
+	}
+    }
+
+    #[inline] fn slot_mut(&mut self, handle: SlotHandle) -> Option<&mut Option<(K, V)>>
+    {
+	let current = *self.generations.get(handle.page)?.get(usize::from(handle.slot))?;
+	if current == handle.generation {
+	    self.map.0.get_mut(handle.page).map(|page| &mut page.0[usize::from(handle.slot)])
+	} else {
+	    None
+	}
+    }
+
+    /// Take the entry out of this slot, invalidating all handles to it.
+    #[inline] fn take(&mut self, page: usize, slot: u8) -> Option<(K, V)>
+    {
+	let entry = self.map.0[page].0[usize::from(slot)].take()?;
+	let generation = &mut self.generations[page][usize::from(slot)];
+	*generation = generation.wrapping_add(1);
+	Some(entry)
+    }
+
+    /// Does this handle point to an entry in the map
+    #[inline] pub fn contains(&self, handle: SlotHandle) -> bool
+    {
+	matches!(self.slot(handle), Some(Some(_)))
+    }
+
+    /// The key and value of the entry `handle` points to
+    #[inline] pub fn get_key_value(&self, handle: SlotHandle) -> Option<(&K, &V)>
+    {
+	self.slot(handle)?.as_ref().map(|(k, v)| (k, v))
+    }
+
+    /// The value of the entry `handle` points to
+    #[inline] pub fn get(&self, handle: SlotHandle) -> Option<&V>
+    {
+	self.get_key_value(handle).map(|(_, v)| v)
+    }
+
+    /// A mutable reference to the value of the entry `handle` points to
+    #[inline] pub fn get_mut(&mut self, handle: SlotHandle) -> Option<&mut V>
+    {
+	self.slot_mut(handle)?.as_mut().map(|(_, v)| v)
+    }
+
+    /// Remove the entry `handle` points to, returning it if the handle was valid.
+    #[inline] pub fn remove(&mut self, handle: SlotHandle) -> Option<(K, V)>
+    {
+	self.slot(handle)?;
+	self.take(handle.page, handle.slot)
+    }
+
+    /// Remove all entries, invalidating all handles.
+    pub fn clear(&mut self)
+    {
+	for page in 0..self.map.0.len()
+	{
+	    for slot in 0..=u8::MAX
+	    {
+		self.take(page, slot);
+	    }
+	}
+    }
+
+    /// An iterator over the handles of all entries along with the entries
+    pub fn handles(&self) -> impl Iterator<Item = (SlotHandle, &K, &V)>
+    {
+	self.map.0.iter().enumerate()
+	    .flat_map(|(page, p)| p.0.iter().zip(0..=u8::MAX).map(move |(kv, slot)| (page, slot, kv)))
+	    .filter_map(move |(page, slot, kv)| kv.as_ref().map(|(k, v)| (self.handle_at(page, slot), k, v)))
+    }
+
+    /// Consume this map into the underlying `Map`
+    #[inline] pub fn into_map(self) -> Map<K, V>
+    {
+	self.map
+    }
+}
+
+impl<K, V> HandleMap<K, V>
+where K: Collapse
+{
+    /// Create a new empty `HandleMap`
+    #[inline] pub fn new() -> Self
+    {
+	Self::from_map(Map::new())
+    }
+
+    /// Wrap a `Map`. All slots start at generation 0.
+    #[inline] pub fn from_map(map: Map<K, V>) -> Self
+    {
+	Self {
+	    generations: vec![[0; MAX]; map.num_pages()],
+	    map,
+	}
+    }
+
+    /// The location of the slot of this key
+    fn position<Q>(&self, key: &Q) -> Option<(usize, u8)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.0.iter().position(|page| matches!(page.search(key), Some((k, _)) if k.borrow() == key))
+	    .map(|page| (page, key.collapse()))
+    }
+
+    /// The location of the slot this key is in or would be inserted into, adding a page if needed.
+    fn position_for(&mut self, key: &K) -> (usize, u8)
+    {
+	let page = self.map.page_for(key);
+	if page == self.generations.len() {
+	    self.generations.push([0; MAX]);
+	}
+	(page, key.collapse())
+    }
+
+    /// A handle to the entry of this key
+    #[inline] pub fn handle<Q>(&self, key: &Q) -> Option<SlotHandle>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.position(key).map(|(page, slot)| self.handle_at(page, slot))
+    }
+
+    /// Insert an entry, returning a handle to it and the previous value of the key if it was present.
+    ///
+    /// If the key was present, existing handles to its entry remain valid and the same handle is returned.
+    pub fn insert(&mut self, key: K, value: V) -> (SlotHandle, Option<V>)
+    {
+	let (page, slot) = self.position_for(&key);
+	let old = self.map.0[page].replace(key, value).map(|(_, v)| v);
+	(self.handle_at(page, slot), old)
+    }
+
+    /// A handle to the entry of this key along with its value, inserting the value returned by `with` if it was not present.
+    pub fn entry_or_insert_with<F>(&mut self, key: K, with: F) -> (SlotHandle, &mut V)
+    where F: FnOnce() -> V
+    {
+	let (page, slot) = self.position_for(&key);
+	let handle = self.handle_at(page, slot);
+	let (_, value) = self.map.0[page].0[usize::from(slot)].get_or_insert_with(move || (key, with()));
+	(handle, value)
+    }
+
+    /// Remove the entry of this key, returning its value if it was present.
+    #[inline] pub fn remove_key<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (page, slot) = self.position(key)?;
+	self.take(page, slot).map(|(_, v)| v)
+    }
+}
+
+impl<K, V> Deref for HandleMap<K, V>
+{
+    type Target = Map<K, V>;
+
+    #[inline] fn deref(&self) -> &Self::Target
+    {
+	&self.map
+    }
+}
+
+impl<K: Collapse, V> Default for HandleMap<K, V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for HandleMap<K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	self.map.fmt(f)
+    }
+}
+
+impl<K: Collapse, V> From<Map<K, V>> for HandleMap<K, V>
+{
+    #[inline] fn from(map: Map<K, V>) -> Self
+    {
+	Self::from_map(map)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn generations()
+    {
+	let mut map = HandleMap::new();
+	let (a, old) = map.insert(1u16, 'a');
+	assert_eq!(old, None);
+	let (b, _) = map.insert(257, 'b');
+	assert_eq!((a.page(), a.slot()), (0, 1));
+	assert_eq!((b.page(), b.slot()), (1, 1));
+
+	assert_eq!(map.insert(1, 'A'), (a, Some('a')));
+	assert_eq!(map.get_key_value(a), Some((&1, &'A')));
+	assert_eq!(map.remove_key(&1), Some('A'));
+	assert!(!map.contains(a));
+
+	// Page 0 slot 1 is free again, but the old handle does not see the new entry
+	let (c, value) = map.entry_or_insert_with(513, || 'c');
+	*value = 'C';
+	assert_eq!((c.page(), c.slot(), c.generation()), (0, 1, 1));
+	assert_eq!(map.get(a), None);
+	assert_eq!(map.get(c), Some(&'C'));
+	assert_eq!(map.remove(a), None);
+	assert_eq!(map.handle(&513), Some(c));
+
+	let (c2, value) = map.entry_or_insert_with(513, || unreachable!());
+	assert_eq!((c2, *value), (c, 'C'));
+
+	map.clear();
+	assert!(!map.contains(b) && !map.contains(c));
+	assert_eq!(map.num_pages(), 2);
+	let (b2, _) = map.insert(257, 'b');
+	assert_eq!((b2.page(), b2.generation()), (0, 2));
+    }
+
+    #[test]
+    fn handles()
+    {
+	let mut map: HandleMap<u16, u16> = (0..600).map(|i| (i, i)).collect::<Map<_, _>>().into();
+	let handles: Vec<_> = map.handles().map(|(h, &k, _)| (h, k)).collect();
+	assert_eq!(handles.len(), 600);
+	for &(handle, key) in handles.iter() {
+	    assert_eq!(map.handle(&key), Some(handle));
+	    *map.get_mut(handle).unwrap() += 1;
+	}
+	assert!(map.iter().all(|&(k, v)| v == k + 1));
+	let (handle, _) = map.insert(768, 0);
+	assert_eq!(handle.page(), 3);
+	assert_eq!(map.remove(handle), Some((768, 0)));
+    }
+}
@@ -45,7 +45,7 @@ impl<'a, K, V> Iterator for PageElements<'a,K,V>
 
     #[inline] fn next(&mut self) -> Option<Self::Item> {
 	//self.0.next().map(Option::as_ref).flatten()
-	while let Some(next) = self.0.next() {
+	for next in self.0.by_ref() {
 	    if let Some(next) = next.as_ref() {
 		return Some(next);
 	    }
@@ -67,7 +67,7 @@ impl<'a, K, V> Iterator for PageElementsMut<'a,K,V>
     type Item = &'a mut (K,V);
 
     #[inline] fn next(&mut self) -> Option<Self::Item> {
-	while let Some(next) = self.0.next() {
+	for next in self.0.by_ref() {
 	    if let Some(next) = next.as_mut() {
 		return Some(next);
 	    }
@@ -0,0 +1,475 @@
+//! `Map`s that record their modifications as an operation log
+//!
+//! A [`JournaledMap`] records every modification made through it as a sequence-numbered [`Record`] in its [`Journal`].
+//! The records can be sent elsewhere (they can be serialised with `serde`, or with this crate's [`codec`](crate::codec)), and [`replay()`]ed onto another `Map` to reproduce the same modifications there.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! use smallmap::journal::{self, JournaledMap};
+//!
+//! let mut primary = JournaledMap::new();
+//! primary.insert("a", 1);
+//! primary.insert("b", 2);
+//! primary.remove(&"a");
+//!
+//! let mut replica = Map::new();
+//! let last = journal::replay(&mut replica, primary.journal().records());
+//! assert_eq!(last, Some(3));
+//! assert_eq!(&replica, &*primary);
+//! ```
+use super::*;
+use codec::{
+    Decode, DecodeError,
+    Encode, EncodeError,
+    Reader, Writer,
+};
+use core::ops::Deref;
+use observe::{ObservedEntry, ObservedMap, Observer};
+
+/// A modification to a `Map`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Op<K, V>
+{
+    /// An entry was inserted, or its value replaced or modified
+    Insert {
+	/// The key of the entry
+	key: K,
+	/// The new value of the entry
+	value: V,
+    },
+    /// An entry was removed
+    Remove {
+	/// The key of the entry
+	key: K,
+    },
+    /// All entries were removed
+    Clear,
+}
+
+impl<K, V> Op<K, V>
+where K: Collapse
+{
+    /// Apply this operation to `map`.
+    pub fn apply(self, map: &mut Map<K,V>)
+    {
+	match self {
+	    Self::Insert { key, value } => {
+		map.insert(key, value);
+	    },
+	    Self::Remove { key } => {
+		map.remove(&key);
+	    },
+	    Self::Clear => map.clear(),
+	}
+    }
+}
+
+/// An operation in a [`Journal`] with its sequence number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Record<K, V>
+{
+    /// The sequence number of the operation. Operations are numbered consecutively from `1`.
+    pub seq: u64,
+    /// The operation
+    pub op: Op<K, V>,
+}
+
+/// A log of the operations made on a [`JournaledMap`].
+///
+/// The journal's records are always in order of sequence number, but may not start at `1` if it has been [`truncate()`](Journal::truncate)d.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Journal<K, V>
+{
+    seq: u64,
+    records: Vec<Record<K, V>>,
+}
+
+impl<K, V> Default for Journal<K, V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K, V> Journal<K, V>
+{
+    /// Create a new empty journal
+    #[inline] pub const fn new() -> Self
+    {
+	Self {
+	    seq: 0,
+	    records: Vec::new(),
+	}
+    }
+
+    /// The sequence number of the last operation recorded, or `0` if there have been none.
+    ///
+    /// This is not reset by truncation.
+    #[inline] pub fn last_seq(&self) -> u64
+    {
+	self.seq
+    }
+
+    /// All records currently in the journal
+    #[inline] pub fn records(&self) -> &[Record<K, V>]
+    {
+	&self.records[..]
+    }
+
+    /// The records of operations after the one numbered `seq`.
+    ///
+    /// If the journal has been truncated past `seq`, the first available records are returned; check the sequence number of the first record to detect this.
+    pub fn since(&self, seq: u64) -> &[Record<K, V>]
+    {
+	let start = self.records.partition_point(|record| record.seq <= seq);
+	&self.records[start..]
+    }
+
+    /// The number of records currently in the journal
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.records.len()
+    }
+
+    /// Are there no records currently in the journal
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.records.is_empty()
+    }
+
+    /// Remove the records of all operations up to and including the one numbered `seq`, e.g. once a checkpoint of the map at that point has been saved.
+    pub fn truncate(&mut self, seq: u64)
+    {
+	let end = self.records.partition_point(|record| record.seq <= seq);
+	self.records.drain(..end);
+    }
+
+    /// Record a new operation
+    fn push(&mut self, op: Op<K, V>)
+    {
+	self.seq += 1;
+	self.records.push(Record { seq: self.seq, op });
+    }
+}
+
+impl<K: Clone, V: Clone> Observer<K, V> for Journal<K, V>
+{
+    #[inline] fn on_insert(&mut self, key: &K, value: &V)
+    {
+	self.push(Op::Insert { key: key.clone(), value: value.clone() });
+    }
+    #[inline] fn on_replace(&mut self, key: &K, _old: &V, new: &V)
+    {
+	self.on_insert(key, new)
+    }
+    #[inline] fn on_modify(&mut self, key: &K, value: &V)
+    {
+	self.on_insert(key, value)
+    }
+    #[inline] fn on_remove(&mut self, key: &K, _value: &V)
+    {
+	self.push(Op::Remove { key: key.clone() });
+    }
+}
+
+/// Apply the operations in `records` to `map` in order, returning the sequence number of the last one applied.
+///
+/// The sequence numbers are not checked for gaps. To catch up a replica, pass the primary's [`Journal::since()`] with the sequence number returned by the last replay.
+pub fn replay<'a, K, V, I>(map: &mut Map<K,V>, records: I) -> Option<u64>
+where K: Collapse + Clone + 'a,
+      V: Clone + 'a,
+      I: IntoIterator<Item = &'a Record<K, V>>
+{
+    records.into_iter().fold(None, |_, record| {
+	record.op.clone().apply(map);
+	Some(record.seq)
+    })
+}
+
+/// A `Map` that records every modification made through it in a [`Journal`].
+///
+/// The map can be read through this wrapper (it dereferences to `Map`), but can only be modified through its own methods.
+/// This is an [`ObservedMap`] whose observer is its journal, with the addition of `clear()` being recorded as a single operation.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JournaledMap<K, V>(ObservedMap<K, V, Journal<K, V>>);
+
+impl<K, V> JournaledMap<K, V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    /// Create a new empty map with an empty journal
+    #[inline] pub fn new() -> Self
+    {
+	Self(ObservedMap::new(Journal::new()))
+    }
+
+    /// Wrap an existing map with an empty journal. Its existing entries are not recorded.
+    #[inline] pub fn from_map(map: Map<K,V>) -> Self
+    {
+	Self(ObservedMap::from_map(map, Journal::new()))
+    }
+
+    /// The journal of operations made on this map
+    #[inline] pub fn journal(&self) -> &Journal<K, V>
+    {
+	self.0.observer()
+    }
+
+    /// A mutable reference to the journal, e.g. to [`truncate()`](Journal::truncate) it.
+    #[inline] pub fn journal_mut(&mut self) -> &mut Journal<K, V>
+    {
+	self.0.observer_mut()
+    }
+
+    /// Consume this wrapper, returning the map and its journal
+    #[inline] pub fn into_parts(self) -> (Map<K,V>, Journal<K, V>)
+    {
+	self.0.into_parts()
+    }
+
+    /// Insert a new key-value entry into this map, returning the pervious value if it was present
+    #[inline] pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	self.0.insert(key, value)
+    }
+
+    /// Get an `ObservedEntry` for the `key` that lets you get, insert, modify or remove its value
+    ///
+    /// Values returned by the entry are recorded with their final value when they are dropped, see [`ObservedValue`].
+    #[inline] pub fn entry(&mut self, key: K) -> ObservedEntry<'_, K, V, Journal<K, V>>
+    {
+	self.0.entry(key)
+    }
+
+    /// Modify the value corresponding to this key in place, if it is in the map.
+    ///
+    /// Returns the result of `f`, or `None` if the key is not in the map.
+    #[inline] pub fn modify<Q, F, T>(&mut self, key: &Q, f: F) -> Option<T>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq,
+	  F: FnOnce(&mut V) -> T
+    {
+	self.0.modify(key, f)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the value if it was present
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.remove(key)
+    }
+
+    /// Keep only the entries for which `keep` returns `true`, removing all others.
+    ///
+    /// Each entry that is kept is recorded with its value afterwards, since `keep` may have modified it.
+    #[inline] pub fn retain<F>(&mut self, keep: F)
+    where F: FnMut(&K, &mut V) -> bool
+    {
+	self.0.retain(keep)
+    }
+
+    /// Remove all entries from this map.
+    pub fn clear(&mut self)
+    {
+	let (map, journal) = self.0.parts_mut();
+	map.clear();
+	journal.push(Op::Clear);
+    }
+}
+
+impl<K: Collapse + Clone, V: Clone> Default for JournaledMap<K, V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K, V> Deref for JournaledMap<K, V>
+{
+    type Target = Map<K,V>;
+
+    #[inline] fn deref(&self) -> &Self::Target
+    {
+	&self.0
+    }
+}
+
+impl<K: Collapse + Clone, V: Clone> core::iter::Extend<(K,V)> for JournaledMap<K, V>
+{
+    #[inline] fn extend<T: IntoIterator<Item = (K,V)>>(&mut self, iter: T)
+    {
+	self.0.extend(iter)
+    }
+}
+
+impl<K: Encode, V: Encode> Encode for Op<K, V>
+{
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	match self {
+	    Self::Insert { key, value } => {
+		0u8.encode(writer)?;
+		key.encode(writer)?;
+		value.encode(writer)
+	    },
+	    Self::Remove { key } => {
+		1u8.encode(writer)?;
+		key.encode(writer)
+	    },
+	    Self::Clear => 2u8.encode(writer),
+	}
+    }
+}
+
+impl<K: Decode, V: Decode> Decode for Op<K, V>
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	match u8::decode(reader)? {
+	    0 => Ok(Self::Insert { key: K::decode(reader)?, value: V::decode(reader)? }),
+	    1 => Ok(Self::Remove { key: K::decode(reader)? }),
+	    2 => Ok(Self::Clear),
+	    tag => Err(DecodeError::InvalidTag(tag)),
+	}
+    }
+}
+
+/// The sequence number is encoded as a variable-length integer, followed by the operation.
+impl<K: Encode, V: Encode> Encode for Record<K, V>
+{
+    fn encode<W: Writer + ?Sized>(&self, writer: &mut W) -> Result<(), EncodeError>
+    {
+	codec::write_varint(writer, self.seq)?;
+	self.op.encode(writer)
+    }
+}
+
+impl<K: Decode, V: Decode> Decode for Record<K, V>
+{
+    fn decode<R: Reader + ?Sized>(reader: &mut R) -> Result<Self, DecodeError>
+    {
+	Ok(Self {
+	    seq: codec::read_varint(reader)?,
+	    op: Op::decode(reader)?,
+	})
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn primary() -> JournaledMap<u16, u16>
+    {
+	let mut map = JournaledMap::new();
+	map.extend((0..600).map(|x| (x, x)));
+	map.insert(5, 50);
+	map.remove(&6);
+	map.modify(&7, |v| *v += 1);
+	map.entry(1000).or_insert(1);
+	map.entry(8).and_modify(|v| *v = 0);
+	map.retain(|&k, _| k % 100 != 0);
+	map
+    }
+
+    #[test]
+    fn replay_equal()
+    {
+	let map = primary();
+	// The 593 entries kept by `retain()` are recorded as modified
+	assert_eq!(map.journal().last_seq(), 600 + 5 + 7 + 593);
+	assert_eq!(map.journal().len(), 1205);
+
+	let mut replica = Map::new();
+	assert_eq!(replay(&mut replica, map.journal().records()), Some(1205));
+	assert_eq!(&replica, &*map);
+    }
+
+    #[test]
+    fn replay_entries()
+    {
+	let mut map = JournaledMap::new();
+	for c in "hello".chars() {
+	    *map.entry(c).or_insert(0) += 1;
+	}
+	*map.entry('z').or_default() += 10;
+
+	let mut replica = Map::new();
+	replay(&mut replica, map.journal().records());
+	assert_eq!(replica.get(&'l'), Some(&2));
+	assert_eq!(replica.get(&'z'), Some(&10));
+	assert_eq!(&replica, &*map);
+    }
+
+    #[test]
+    fn replay_retain()
+    {
+	let mut map = primary();
+	map.retain(|&k, v| {
+	    *v += 100;
+	    k % 2 == 0
+	});
+
+	let mut replica = Map::new();
+	replay(&mut replica, map.journal().records());
+	assert_eq!(replica.get(&4), Some(&104));
+	assert_eq!(&replica, &*map);
+    }
+
+    #[test]
+    fn clear_truncate()
+    {
+	let mut map = primary();
+	let mut replica = Map::new();
+	let seq = replay(&mut replica, map.journal().records()).unwrap();
+	map.journal_mut().truncate(seq);
+	assert!(map.journal().is_empty());
+
+	map.clear();
+	map.insert(1, 1);
+	assert_eq!(map.journal().records(), &[
+	    Record { seq: seq + 1, op: Op::Clear },
+	    Record { seq: seq + 2, op: Op::Insert { key: 1, value: 1 } },
+	]);
+	assert_eq!(map.journal().since(seq + 1).len(), 1);
+	replay(&mut replica, map.journal().since(seq));
+	// `clear()` keeps the replica's pages, so only the entries are the same
+	assert_eq!(replica.diff(&map).count(), 0);
+	assert_eq!(replica.len(), 1);
+    }
+
+    #[test]
+    fn encode()
+    {
+	let map = primary();
+	let records = map.journal().records();
+	let mut bytes = Vec::new();
+	for record in records {
+	    record.encode(&mut bytes).unwrap();
+	}
+	let mut reader = &bytes[..];
+	let decoded: Vec<Record<u16, u16>> = (0..records.len()).map(|_| Record::decode(&mut reader).unwrap()).collect();
+	assert!(reader.is_empty());
+	assert_eq!(&decoded[..], records);
+
+	assert!(matches!(Op::<u8, u8>::decode(&mut &[3u8][..]), Err(DecodeError::InvalidTag(3))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde()
+    {
+	let map = primary();
+	let json = serde_json::to_string(map.journal()).expect("ser failed");
+	let journal: Journal<u16, u16> = serde_json::from_str(&json).expect("de failed");
+	assert_eq!(&journal, map.journal());
+    }
+}
@@ -0,0 +1,263 @@
+//! Inspecting how entries are laid out in a `Map`'s pages
+use super::*;
+use core::fmt::{self, Debug, Display,};
+
+/// An adaptor that renders the page layout of a `Map`.
+///
+/// Each page is printed on its own line, listing only its occupied slots as `slot: key (depth)`, where `depth` is the number of entries (across all pages) in that slot.
+///
+/// See [`Map::layout()`](Map::layout).
+#[derive(Clone, Copy)]
+pub struct Layout<'a, K, V>(&'a Map<K,V>);
+
+impl<K,V> Map<K,V>
+{
+    /// An adaptor that renders how entries in this map are laid out in its pages.
+    ///
+    /// # Example
+    /// ```
+    /// # use smallmap::*;
+    /// let map = smallmap!{ {1u8 => "one"}, {2u8 => "two"} };
+    /// assert_eq!(map.layout().to_string(), "page 0 (2/256): [1: 1 (1), 2: 2 (1)]");
+    /// ```
+    #[inline] pub fn layout(&self) -> Layout<'_, K, V>
+    {
+	Layout(self)
+    }
+}
+
+impl<'a, K, V> Layout<'a, K, V>
+{
+    /// The number of entries in this slot across all pages
+    fn depth(&self, slot: usize) -> usize
+    {
+	(self.0).0.iter().filter(|page| page.0[slot].is_some()).count()
+    }
+}
+
+impl<'a, K: Debug, V> Display for Layout<'a, K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	for (i, page) in (self.0).0.iter().enumerate()
+	{
+	    if i > 0 {
+		writeln!(f)?;
+	    }
+	    write!(f, "page {} ({}/{}): [", i, page.0.iter().flatten().count(), MAX)?;
+	    for (j, (slot, (key, _))) in page.0.iter().enumerate().filter_map(|(slot, kv)| kv.as_ref().map(|kv| (slot, kv))).enumerate()
+	    {
+		if j > 0 {
+		    write!(f, ", ")?;
+		}
+		write!(f, "{}: {:?} ({})", slot, key, self.depth(slot))?;
+	    }
+	    write!(f, "]")?;
+	}
+	Ok(())
+    }
+}
+
+impl<'a, K: Debug, V> Debug for Layout<'a, K, V>
+{
+    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	Display::fmt(self, f)
+    }
+}
+
+/// A violation of the invariants of a `Map`'s page layout.
+///
+/// See [`Map::validate()`](Map::validate).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LayoutError
+{
+    /// The map has no pages. A `Map` must always have at least one page.
+    NoPages,
+    /// The entry in this slot of this page has a key that does not collapse to the slot's index.
+    Misplaced {
+	/// The index of the page containing the entry
+	page: usize,
+	/// The slot the entry is in
+	slot: u8,
+	/// The slot the entry's key collapses to
+	expected: u8,
+    },
+    /// The key of the entry in this slot is also present in the same slot of an earlier page.
+    Duplicate {
+	/// The index of the page containing the first occurance of the key
+	first: usize,
+	/// The index of the page containing the duplicate
+	page: usize,
+	/// The slot both entries are in
+	slot: u8,
+    },
+}
+
+impl Display for LayoutError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	match self {
+	    Self::NoPages => write!(f, "map has no pages"),
+	    Self::Misplaced { page, slot, expected } => write!(f, "entry in slot {} of page {} has a key that collapses to slot {}", slot, page, expected),
+	    Self::Duplicate { first, page, slot } => write!(f, "entry in slot {} of page {} duplicates the key in page {}", slot, page, first),
+	}
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LayoutError{}
+
+impl<K,V> Page<K,V>
+where K: Collapse
+{
+    /// Find the first entry in this page whose key does not collapse to its slot, returning its slot and the slot it should be in.
+    pub(crate) fn find_misplaced(&self) -> Option<(u8, u8)>
+    {
+	self.0.iter().zip(0..=u8::MAX)
+	    .filter_map(|(kv, slot)| kv.as_ref().map(|(k, _)| (slot, k.collapse())))
+	    .find(|&(slot, expected)| slot != expected)
+    }
+}
+
+impl<K,V> Map<K,V>
+where K: Collapse
+{
+    /// Check that every entry is in the slot its key collapses to, and that no key is present more than once.
+    ///
+    /// Maps that are only built and modified through their safe API always pass, as does deserialising a layout (which is validated.) A violation means the map was built with `unsafe` code, e.g. by `transmute`ing between key types with differing `collapse()` outputs (see [`Primitive`](crate::Primitive).)
+    /// The first violation found is returned. See [`repair()`](Map::repair) to fix them.
+    pub fn validate(&self) -> Result<(), LayoutError>
+    {
+	if self.0.is_empty() {
+	    return Err(LayoutError::NoPages);
+	}
+	for (page, p) in self.0.iter().enumerate()
+	{
+	    if let Some((slot, expected)) = p.find_misplaced() {
+		return Err(LayoutError::Misplaced { page, slot, expected });
+	    }
+	}
+	for slot in 0..MAX
+	{
+	    for (page, p) in self.0.iter().enumerate()
+	    {
+		if let Some((key, _)) = &p.0[slot] {
+		    if let Some(first) = self.0[..page].iter().position(|q| matches!(&q.0[slot], Some((k, _)) if k == key)) {
+			return Err(LayoutError::Duplicate { first, page, slot: slot as u8 });
+		    }
+		}
+	    }
+	}
+	Ok(())
+    }
+
+    /// Fix any violations that [`validate()`](Map::validate) would report.
+    ///
+    /// Misplaced entries are moved to the slots their keys collapse to. If a key is present more than once, the entry that `get()` would have found is kept and the others are dropped.
+    /// Returns the number of entries that were moved or dropped.
+    pub fn repair(&mut self) -> usize
+    {
+	if self.0.is_empty() {
+	    self.0.push(Page::new());
+	}
+	let mut misplaced = Vec::new();
+	for page in self.0.iter_mut()
+	{
+	    for (slot, kv) in page.0.iter_mut().enumerate()
+	    {
+		if matches!(kv, Some((k, _)) if usize::from(k.collapse()) != slot) {
+		    misplaced.extend(kv.take());
+		}
+	    }
+	}
+	let mut fixed = misplaced.len();
+	for slot in 0..MAX
+	{
+	    for page in 1..self.0.len()
+	    {
+		let (before, after) = self.0.split_at_mut(page);
+		let kv = &mut after[0].0[slot];
+		if matches!(kv, Some((key, _)) if before.iter().any(|q| matches!(&q.0[slot], Some((k, _)) if k == key))) {
+		    *kv = None;
+		    fixed += 1;
+		}
+	    }
+	}
+	for (key, value) in misplaced
+	{
+	    if !self.contains_key(&key) {
+		self.insert(key, value);
+	    }
+	}
+	fixed
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn debug()
+    {
+	let map = smallmap!{ {1u8 => 'a'}, {2u8 => 'b'} };
+	assert_eq!(format!("{:?}", map), "{1: 'a', 2: 'b'}");
+	assert_eq!(format!("{:?}", map.pages().next().unwrap()), "{1: (1, 'a'), 2: (2, 'b')}");
+    }
+
+    #[test]
+    fn layout()
+    {
+	let map = smallmap!{ {1u16 => ()}, {257u16 => ()}, {2u16 => ()} };
+	assert_eq!(map.layout().to_string(), "page 0 (2/256): [1: 1 (2), 2: 2 (1)]\npage 1 (1/256): [1: 257 (2)]");
+    }
+
+    #[test]
+    fn validate_repair()
+    {
+	let map: Map<u16, u16> = (0..300).map(|x| (x, x)).collect();
+	assert_eq!(map.validate(), Ok(()));
+
+	// Keys in `Map<u16, _>` collapse to `x % 256`, keys in `Map<Primitive<u16>, _>` collapse to the XOR of both bytes.
+	let mut map: Map<Primitive<u16>, u16> = unsafe { core::mem::transmute(map) };
+	assert_eq!(map.validate(), Err(LayoutError::Misplaced { page: 1, slot: 0, expected: 1 }));
+	assert!(map.repair() > 0);
+	assert_eq!(map.validate(), Ok(()));
+	assert_eq!(map.len(), 300);
+	for x in 0..300 {
+	    assert_eq!(map.get(&Primitive::new(x)), Some(&x));
+	}
+    }
+
+    #[test]
+    fn validate_after_reinsert()
+    {
+	let mut map: Map<u16, u16> = Map::new();
+	map.insert(1, 1);
+	map.insert(257, 257);
+	map.remove(&1);
+	map.insert(257, 0);
+	*map.entry(257).or_insert(1) += 1;
+	map.insert(1, 1);
+	assert_eq!(map.validate(), Ok(()));
+	assert_eq!(map.len(), 2);
+	assert_eq!(map[&257], 1);
+    }
+
+    #[test]
+    fn duplicate()
+    {
+	let mut map = smallmap!{ {1u8 => 'a'}, {2u8 => 'b'} };
+	let mut page = Page::new();
+	page.replace(1u8, 'c');
+	map.0.push(page);
+	assert_eq!(map.validate(), Err(LayoutError::Duplicate { first: 0, page: 1, slot: 1 }));
+	assert_eq!(map.repair(), 1);
+	assert_eq!(map.validate(), Ok(()));
+	assert_eq!(map.get(&1), Some(&'a'));
+	assert_eq!(map.len(), 2);
+    }
+}
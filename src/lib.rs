@@ -13,7 +13,7 @@
 //! {
 //!     let mut map = Map::new();
 //!     for x in chars.chars() {
-//! 	    *map.entry(x).or_insert(0usize) += 1;	
+//!         *map.entry(x).or_insert(0usize) += 1;
 //!     }
 //! 
 //!     map.into_iter().max_by_key(|&(_, v)| v).unwrap_or_default()
@@ -32,6 +32,7 @@
 
 #[cfg(all(nightly, test))] extern crate test;
 extern crate alloc;
+#[cfg(all(feature = "std", not(test)))] extern crate std;
 
 const MAX: usize = 256;
 
@@ -44,23 +45,76 @@ use iter::*;
 pub mod entry;
 pub use entry::Entry;
 
+pub mod set;
+pub use set::Set;
+
 pub mod space;
 
+pub mod stats;
+pub use stats::Stats;
+
+pub mod layout;
+pub use layout::LayoutError;
+
+pub mod codec;
+
+pub mod archive;
+
+pub mod persistent;
+pub use persistent::PersistentMap;
+
+pub mod transaction;
+pub use transaction::Transaction;
+
+pub mod diff;
+
+pub mod observe;
+pub use observe::{ObservedMap, ObservedValue, Observer};
+
+pub mod journal;
+
+pub mod trie;
+pub use trie::TrieMap;
+
+pub mod counter;
+pub use counter::Counter;
+
+pub mod multimap;
+pub use multimap::MultiMap;
+
+pub mod bimap;
+pub use bimap::BiMap;
+
+pub mod lru;
+pub use lru::LruMap;
+
+pub mod ttl;
+pub use ttl::{TtlMap, Clock};
+
+pub mod default;
+pub use default::DefaultMap;
+
+pub mod handle;
+pub use handle::{HandleMap, SlotHandle};
+
+pub mod cursor;
+pub use cursor::CursorMut;
+
+#[cfg(feature = "serde")]
+pub mod serde_layout;
+#[cfg(feature = "serde")]
+pub mod serde_borrow;
+
 pub mod primitive;
 pub use primitive::Primitive;
 
 mod init;
+mod convert;
 
 mod private {
     pub trait Sealed{}
 }
 
-/// A smallmap set.
-///
-/// Can be used to quickly insert or remove a key only, with no value; and can be used to see if this key is present.
-///
-/// Any map type with a zero-sized value is essentially a set.
-pub type Set<T> = Map<T,()>;
 
 /// A helper macro for creating `Map` instances with or without pre-set entries.
 ///
@@ -98,6 +152,38 @@ pub type Set<T> = Map<T,()>;
     }
 }
 
+/// A helper macro for creating `Set` instances with or without pre-set values.
+///
+/// # Create empty set
+/// With no parameters this just calls `Set::new()`.
+/// ```
+/// # use smallmap::*;
+/// let set: Set<i32> = smallset!();
+/// let set2: Set<i32> = Set::new();
+/// assert_eq!(set, set2);
+/// ```
+/// # Create with values
+/// You can specify some values to pre-insert, seperated by commas.
+/// ```
+/// # use smallmap::*;
+/// let set = smallset!["One", "Two", "Three"];
+/// assert!(set.contains("Two"));
+/// ```
+#[macro_export ]macro_rules! smallset {
+    () => {
+	$crate::Set::new()
+    };
+    ($($value:expr),* $(,)?) => {
+	{
+	    let mut set = $crate::Set::new();
+	    $(
+		set.insert($value);
+	    )*
+		set
+	}
+    }
+}
+
 
 /// Trait for types that can be used as `Map` keys.
 ///
@@ -140,7 +226,13 @@ where K: Collapse
     /// This is a count that iterates over all slots, if possible store it in a temporary instead of re-calling it many times.
     pub fn len(&self) -> usize
     {
-	self.0.iter().map(Option::as_ref).filter_map(core::convert::identity).count()
+	self.0.iter().flatten().count()
+    }
+
+    /// Does this page contain no entries
+    pub fn is_empty(&self) -> bool
+    {
+	self.0.iter().all(Option::is_none)
     }
 
     /// An iterator over all entries currently in this page
@@ -155,20 +247,20 @@ where K: Collapse
 	PageElementsMut(self.0.iter_mut())
     }
     
-    fn search<Q: ?Sized>(&self, key: &Q) -> &Option<(K,V)>
-    where Q: Collapse
+    fn search<Q>(&self, key: &Q) -> &Option<(K,V)>
+    where Q: ?Sized + Collapse
     {
 	&self.0[usize::from(key.collapse())]
     }
-    fn search_mut<Q: ?Sized>(&mut self, key: &Q) -> &mut Option<(K,V)>
-    where Q: Collapse
+    fn search_mut<Q>(&mut self, key: &Q) -> &mut Option<(K,V)>
+    where Q: ?Sized + Collapse
     {
 	&mut self.0[usize::from(key.collapse())]
     }
 
     fn replace(&mut self, k: K, v: V) -> Option<(K,V)>
     {
-	core::mem::replace(&mut self.0[usize::from(k.collapse())], Some((k,v)))
+	self.0[usize::from(k.collapse())].replace((k,v))
     }
 }
 
@@ -211,19 +303,115 @@ where K: Collapse
 }
 
 /// A small hashtable-like map with byte sized key indecies.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+///
+/// The `Debug` output of this type is that of a regular map (`{key: value, ...}`). To see how entries are laid out in pages, see [`layout()`](Map::layout).
+///
+/// Maps are equal (and hash the same) if they have the same entries, regardless of which pages the entries are in.
+#[derive(Clone)]
 // TODO: Replace with `SmallVec<[Page<TKey, TValue>; 1]>` when feature that adds `smallvec` is enabled (this will allocate the first page on the stack, and the rest on the heap.
 pub struct Map<TKey, TValue>(Vec<Page<TKey,TValue>>);
 
+impl<K: core::fmt::Debug, V: core::fmt::Debug> core::fmt::Debug for Map<K,V>
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result
+    {
+	f.debug_map().entries(self.0.iter().flat_map(|page| page.0.iter().flatten()).map(|(k, v)| (k, v))).finish()
+    }
+}
+
+/// Do both lists of pages have the same entries, regardless of which pages they are in.
+///
+/// An entry can only be in the slot its key collapses to, so it is looked for in the same slot of each of `theirs`.
+pub(crate) fn pages_eq<K, V, P>(ours: &[P], theirs: &[P]) -> bool
+where K: PartialEq,
+      V: PartialEq,
+      P: Borrow<Page<K,V>>
+{
+    let len = |pages: &[P]| pages.iter().flat_map(|page| page.borrow().0.iter().flatten()).count();
+    len(ours) == len(theirs) && ours.iter().all(|page| page.borrow().0.iter().enumerate().all(|(slot, entry)| match entry {
+	Some((k, v)) => theirs.iter().any(|page| matches!(&page.borrow().0[slot], Some((ok, ov)) if ok == k && ov == v)),
+	None => true,
+    }))
+}
+
+/// Hash the entries of a list of pages independently of which pages they are in.
+///
+/// Entries always collapse to the same slot, so the set of entries in each slot does not depend on layout, only their order does. Each slot is hashed as the number of its entries and the sum of their hashes (with a fixed FNV-1a hasher, so that the sum does not depend on `H`'s state.)
+pub(crate) fn hash_pages<K, V, P, H>(pages: &[P], state: &mut H)
+where K: Hash,
+      V: Hash,
+      P: Borrow<Page<K,V>>,
+      H: Hasher
+{
+    struct Fnv(u64);
+    impl Hasher for Fnv
+    {
+	#[inline] fn finish(&self) -> u64
+	{
+	    self.0
+	}
+	#[inline] fn write(&mut self, bytes: &[u8])
+	{
+	    for &byte in bytes {
+		self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x100000001b3);
+	    }
+	}
+    }
+
+    for slot in 0..MAX
+    {
+	let (count, sum) = pages.iter().filter_map(|page| page.borrow().0[slot].as_ref())
+	    .fold((0usize, 0u64), |(count, sum), entry| {
+		let mut hasher = Fnv(0xcbf29ce484222325);
+		entry.hash(&mut hasher);
+		(count + 1, sum.wrapping_add(hasher.finish()))
+	    });
+	state.write_usize(count);
+	state.write_u64(sum);
+    }
+}
+
+impl<K: PartialEq, V: PartialEq> PartialEq for Map<K,V>
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &Self) -> bool
+    {
+	pages_eq(&self.0[..], &other.0[..])
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for Map<K,V>{}
+
+impl<K: Hash, V: Hash> Hash for Map<K,V>
+{
+    #[inline] fn hash<H: Hasher>(&self, state: &mut H)
+    {
+	hash_pages(&self.0[..], state)
+    }
+}
+
 #[cfg(feature = "serde")]
 struct MapVisitor<TKey, TValue> {
 	_pd: core::marker::PhantomData<(TKey, TValue)>,
 }
 
+#[cfg(feature = "serde")]
+struct MapInPlaceVisitor<'a, TKey, TValue>(&'a mut Map<TKey, TValue>);
+
+/// # Borrowed keys
+/// Keys that borrow from the input, such as `&'de str` and `&'de [u8]`, are supported; but only if the format can provide the key without copying it (e.g. a JSON string key containing escape sequences cannot be borrowed, and will fail to deserialise.)
+/// `Cow<'de, str>` and `Cow<'de, [u8]>` keys never fail this way, but always allocate with this impl. To have them borrow when possible, see [`serde_borrow`](crate::serde_borrow).
+///
+/// # In-place deserialisation
+/// `deserialize_in_place()` clears the existing map and inserts into its already allocated pages, instead of allocating new ones.
 #[cfg(feature = "serde")]
 impl<'de, TKey, TValue> serde::de::Deserialize<'de> for Map<TKey, TValue> where TKey: Collapse + serde::Deserialize<'de>, TValue: serde::Deserialize<'de> {
 	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: serde::Deserializer<'de> {
-		deserializer.deserialize_map(MapVisitor { _pd: core::marker::PhantomData::default() })
+		deserializer.deserialize_map(MapVisitor { _pd: core::marker::PhantomData })
+	}
+
+	fn deserialize_in_place<D>(deserializer: D, place: &mut Self) -> Result<(), D::Error> where D: serde::Deserializer<'de> {
+		deserializer.deserialize_map(MapInPlaceVisitor(place))
 	}
 }
 
@@ -237,7 +425,7 @@ impl<'de, TKey, TValue> serde::de::Visitor<'de> for MapVisitor<TKey, TValue> whe
 	}
 
 	fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
-		let mut map = Map::with_capacity(access.size_hint().unwrap_or(1));
+		let mut map = Map::with_capacity(access.size_hint().unwrap_or(0) / MAX + 1);
 		while let Some((key, value)) = access.next_entry()? {
 			map.insert(key, value);
 		}
@@ -245,6 +433,23 @@ impl<'de, TKey, TValue> serde::de::Visitor<'de> for MapVisitor<TKey, TValue> whe
 	}
 }
 
+#[cfg(feature = "serde")]
+impl<'a, 'de, TKey, TValue> serde::de::Visitor<'de> for MapInPlaceVisitor<'a, TKey, TValue> where TKey: Collapse + serde::Deserialize<'de>, TValue: serde::Deserialize<'de> {
+	type Value = ();
+
+	fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+		formatter.write_str("A map")
+	}
+
+	fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error> where A: serde::de::MapAccess<'de> {
+		self.0.clear();
+		while let Some((key, value)) = access.next_entry()? {
+			self.0.insert(key, value);
+		}
+		Ok(())
+	}
+}
+
 #[cfg(feature = "serde")]
 impl<TKey, TValue> serde::Serialize for Map<TKey, TValue> where TKey: Collapse + serde::Serialize, TValue: serde::Serialize {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
@@ -259,9 +464,10 @@ impl<TKey, TValue> serde::Serialize for Map<TKey, TValue> where TKey: Collapse +
 impl<K,V> Map<K,V>
 {
     /// Returns the currently allocated size of the map in bytes (including currently unused reserved space.)
+    ///
+    /// See [`stats()`](Map::stats) for a more detailed breakdown of how this space is used.
     #[inline(always)]
-    #[allow(dead_code)] // Used in test cases, but compiler still warns about it
-    pub(crate) fn internal_size_bytes(&self) -> usize
+    pub fn internal_size_bytes(&self) -> usize
     {
 	self.0.capacity() * core::mem::size_of::<Page<K,V>>()
 	//self.0.iter().map(core::mem::size_of_val).sum::<usize>()
@@ -277,39 +483,71 @@ where K: Collapse
 	self.0.push(Page::new());
 	&mut self.0[len]
     }
-    #[inline(always)] fn fuck_entry(&mut self, key: K) -> Option<Entry<'_, K, V>>
+    /// The index of the page that the entry for this key is in, or would be inserted into, adding a new page if there is no space for it.
+    ///
+    /// An existing entry for the key is found on any page, even if an earlier page has a vacant slot for it (e.g. after a removal.)
+    pub(crate) fn page_for(&mut self, key: &K) -> usize
     {
-	for page in self.0.iter_mut()
+	let mut vacant = None;
+	for (i, page) in self.0.iter().enumerate()
 	{
-	    let re = page.search_mut(&key);
-	    match  re {
-		Some((ref ok, _)) if key.eq(ok.borrow()) => {
-		    return Some(Entry::Occupied(entry::OccupiedEntry(re)));
-		},
-		None => {
-		    return Some(Entry::Vacant(entry::VacantEntry(re, key)));
-		},
+	    match page.search(key) {
+		Some((k, _)) if k == key => return i,
+		None if vacant.is_none() => vacant = Some(i),
 		_ => (),
 	    }
 	}
-	None
+	vacant.unwrap_or_else(|| {
+	    self.new_page();
+	    self.0.len() - 1
+	})
     }
 
     /// Get an `Entry` for the `key` that lets you get or insert the value
     pub fn entry(&mut self, key: K) -> Entry<'_, K, V>
     {
-	// somehow this is faster than using index, even though here we search twice????? i don't know why but there you go
-	if let None =  self.0.iter()
-	    .filter(|x| x.search(&key).as_ref().and_then(|(k, v)| if k==&key {None} else {Some((k,v))}).is_none())
-	    .next() {
-		self.new_page();
+	let page = self.page_for(&key);
+	let slot = self.0[page].search_mut(&key);
+	match slot {
+	    Some(_) => Entry::Occupied(entry::OccupiedEntry(slot)),
+	    None => Entry::Vacant(entry::VacantEntry(slot, key)),
+	}
+    }
+    /// Keep only the entries for which `keep` returns `true`, removing all others.
+    ///
+    /// Pages are not removed even if they become empty, see [`clean()`](Map::clean).
+    pub fn retain<F>(&mut self, mut keep: F)
+    where F: FnMut(&K, &mut V) -> bool
+    {
+	for slot in self.0.iter_mut().flat_map(|page| page.0.iter_mut())
+	{
+	    if let Some((k, v)) = slot {
+		if !keep(k, v) {
+		    *slot = None;
+		}
 	    }
-	self.fuck_entry(key).unwrap()
+	}
     }
+    /// Remove all entries from this map.
+    ///
+    /// The allocated pages are kept to be reused. To remove them as well, call [`clean()`](Map::clean) afterwards.
+    pub fn clear(&mut self)
+    {
+	for page in self.0.iter_mut()
+	{
+	    page.0.iter_mut().for_each(|slot| *slot = None);
+	}
+    }
+
     /// Remove all empty pages from this instance.
+    ///
+    /// If all pages are empty, a single blank page is kept.
     pub fn clean(&mut self)
     {
-	self.0.retain(|x| x.len() >= 1);
+	self.0.retain(|x| !x.is_empty());
+	if self.0.is_empty() {
+	    self.new_page();
+	}
     }
 
     /// The number of entries currently in this map
@@ -322,7 +560,7 @@ where K: Collapse
     /// Is this map empty
     pub fn is_empty(&self) -> bool
     {
-	self.0[0].iter().next().is_none()
+	self.0.iter().all(Page::is_empty)
     }
     /// The number of pages currently in this map
     pub fn num_pages(&self) -> usize
@@ -396,9 +634,9 @@ where K: Collapse
     }
 
     /// Get a mutable reference of the value corresponding to this key if it is in the map.
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where K: Borrow<Q>,
-	  Q: Collapse + Eq
+	  Q: ?Sized + Collapse + Eq
     {
 	for page in self.0.iter_mut()
 	{
@@ -413,17 +651,17 @@ where K: Collapse
     }
 
     /// Search the map for entry corresponding to this key
-    #[inline] pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
     where K: Borrow<Q>,
-	  Q: Collapse + Eq
+	  Q: ?Sized + Collapse + Eq
     {
 	self.get(key).is_some()
     }
 
     /// Get a reference of the value corresponding to this key if it is in the map.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where K: Borrow<Q>,
-	  Q: Collapse + Eq
+	  Q: ?Sized + Collapse + Eq
     {
 	for page in self.0.iter()
 	{
@@ -437,17 +675,42 @@ where K: Collapse
 	None
     }
 
+    /// Get a reference to the key and the value corresponding to this key if it is in the map.
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	for page in self.0.iter()
+	{
+	    match page.search(key) {
+		Some((ref ok, ov)) if key.eq(ok.borrow()) => {
+		    return Some((ok, ov));
+		},
+		_ => (),
+	    }
+	}
+	None
+    }
+
     /// Remove the entry corresponding to this key in the map, returning the value if it was present
-    pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the key-value pair if it was present
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
     where K: Borrow<Q>,
-	  Q: Collapse + Eq
+	  Q: ?Sized + Collapse + Eq
     {
 	for page in self.0.iter_mut()
 	{
 	    let v = page.search_mut(key);
 	    match v {
 		Some((ref ok, _)) if key.eq(ok.borrow()) => {
-		    return v.take().map(|(_, v)| v);
+		    return v.take();
 		},
 		_ => (),
 	    }
@@ -458,24 +721,14 @@ where K: Collapse
     /// Insert a new key-value entry into this map, returning the pervious value if it was present
     pub fn insert(&mut self, key: K, value: V) -> Option<V>
     {
-	for page in self.0.iter_mut()
-	{
-	    match page.search_mut(&key) {
-		Some((ref ok, ov)) if ok.eq(&key) => {
-		    return Some(core::mem::replace(ov, value));
-		},
-		empty @ None => {
-		    return empty.replace((key, value))
-			.map(|(_, v)| v);
-		},
-		_ => (),
-	    }
+	let page = self.page_for(&key);
+	match self.0[page].search_mut(&key) {
+	    Some((_, ov)) => Some(core::mem::replace(ov, value)),
+	    empty @ None => {
+		*empty = Some((key, value));
+		None
+	    },
 	}
-
-	let mut page = Page::new();
-	page.replace(key, value);
-	self.0.push(page);
-	None
     }
     
     /// Consume this `Map` by swapping its keys and values around.
@@ -0,0 +1,434 @@
+//! A bounded least-recently-used cache, built on `Map`.
+//!
+//! Each entry's value is stored in its slot alongside links to the slots of the entries used just before and after it, so looking up an entry and moving it to the front of the recency list never needs more than the key's collapse index.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut cache = LruMap::new(2);
+//! cache.insert('a', 1);
+//! cache.insert('b', 2);
+//! assert_eq!(cache.get(&'a'), Some(&1)); // 'a' is now the most recently used
+//! cache.insert('c', 3); // evicts 'b'
+//!
+//! assert!(!cache.contains_key(&'b'));
+//! assert_eq!(cache.iter().collect::<Vec<_>>(), vec![(&'c', &3), (&'a', &1)]);
+//! ```
+use super::*;
+use core::fmt::{self, Debug,};
+
+/// The location of an entry's slot in the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Pos
+{
+    page: usize,
+    slot: u8,
+}
+
+/// A value with the links to its neighbours in the recency list.
+#[derive(Debug, Clone)]
+struct Node<V>
+{
+    value: V,
+    /// The next more recently used entry
+    prev: Option<Pos>,
+    /// The next less recently used entry
+    next: Option<Pos>,
+}
+
+/// A map with a fixed capacity that evicts its least recently used entry when it is full.
+///
+/// Entries are "used" when they are inserted, or accessed through `get()` or `get_mut()`. Entries never move between slots once inserted, so removed entries leave their slots empty to be reused by later insertions.
+#[derive(Clone)]
+pub struct LruMap<K, V>
+{
+    map: Map<K, Node<V>>,
+    /// The most recently used entry
+    head: Option<Pos>,
+    /// The least recently used entry
+    tail: Option<Pos>,
+    len: usize,
+    capacity: usize,
+}
+
+impl<K, V> LruMap<K, V>
+{
+    #[inline] fn slot(&self, pos: Pos) -> &(K, Node<V>)
+    {
+	self.map.0[pos.page].0[usize::from(pos.slot)].as_ref().expect("LRU link to empty slot")
+    }
+
+    #[inline] fn node_mut(&mut self, pos: Pos) -> &mut Node<V>
+    {
+	&mut self.map.0[pos.page].0[usize::from(pos.slot)].as_mut().expect("LRU link to empty slot").1
+    }
+
+    /// Remove the entry at `pos` from the recency list, leaving it in its slot.
+    fn unlink(&mut self, pos: Pos)
+    {
+	let (prev, next) = {
+	    let node = self.node_mut(pos);
+	    (node.prev.take(), node.next.take())
+	};
+	match prev {
+	    Some(prev) => self.node_mut(prev).next = next,
+	    None => self.head = next,
+	}
+	match next {
+	    Some(next) => self.node_mut(next).prev = prev,
+	    None => self.tail = prev,
+	}
+    }
+
+    /// Make the (unlinked) entry at `pos` the most recently used.
+    fn link_front(&mut self, pos: Pos)
+    {
+	let head = self.head.replace(pos);
+	self.node_mut(pos).next = head;
+	match head {
+	    Some(head) => self.node_mut(head).prev = Some(pos),
+	    None => self.tail = Some(pos),
+	}
+    }
+
+    /// Unlink and take the entry at `pos` out of its slot.
+    fn take(&mut self, pos: Pos) -> (K, V)
+    {
+	self.unlink(pos);
+	self.len -= 1;
+	let (key, node) = self.map.0[pos.page].0[usize::from(pos.slot)].take().expect("LRU link to empty slot");
+	(key, node.value)
+    }
+
+    /// The maximum number of entries in the map
+    #[inline] pub fn capacity(&self) -> usize
+    {
+	self.capacity
+    }
+
+    /// The number of entries in the map
+    ///
+    /// Unlike `Map::len()`, this is not an iterating count.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is the map empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// An iterator over all entries from the most to the least recently used
+    #[inline] pub fn iter(&self) -> Iter<'_, K, V>
+    {
+	Iter {
+	    map: self,
+	    next: self.head,
+	    remaining: self.len,
+	}
+    }
+
+    /// An iterator over all keys from the most to the least recently used
+    #[inline] pub fn keys(&self) -> impl Iterator<Item = &K>
+    {
+	self.iter().map(|(k, _)| k)
+    }
+
+    /// An iterator over all values from the most to the least recently used
+    #[inline] pub fn values(&self) -> impl Iterator<Item = &V>
+    {
+	self.iter().map(|(_, v)| v)
+    }
+
+    /// The least recently used entry, without using it
+    #[inline] pub fn peek_lru(&self) -> Option<(&K, &V)>
+    {
+	self.tail.map(|pos| {
+	    let (k, node) = self.slot(pos);
+	    (k, &node.value)
+	})
+    }
+
+    /// Remove and return the least recently used entry
+    #[inline] pub fn pop_lru(&mut self) -> Option<(K, V)>
+    {
+	self.tail.map(|pos| self.take(pos))
+    }
+
+    /// Change the capacity of the map, evicting least recently used entries until it fits.
+    ///
+    /// # Panics
+    /// If `capacity` is 0.
+    pub fn resize(&mut self, capacity: usize)
+    {
+	assert!(capacity > 0, "LruMap capacity must be non-zero");
+	while self.len > capacity {
+	    self.pop_lru();
+	}
+	self.capacity = capacity;
+    }
+}
+
+impl<K, V> LruMap<K, V>
+where K: Collapse
+{
+    /// Create a new empty `LruMap` that can hold up to `capacity` entries.
+    ///
+    /// # Panics
+    /// If `capacity` is 0.
+    pub fn new(capacity: usize) -> Self
+    {
+	assert!(capacity > 0, "LruMap capacity must be non-zero");
+	Self {
+	    map: Map::new(),
+	    head: None,
+	    tail: None,
+	    len: 0,
+	    capacity,
+	}
+    }
+
+    /// The location of the entry for this key
+    fn position<Q>(&self, key: &Q) -> Option<Pos>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.0.iter().position(|page| matches!(page.search(key), Some((k, _)) if k.borrow() == key))
+	    .map(|page| Pos { page, slot: key.collapse() })
+    }
+
+    /// Make the entry for this key the most recently used, returning its location.
+    fn touch<Q>(&mut self, key: &Q) -> Option<Pos>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let pos = self.position(key)?;
+	if self.head != Some(pos) {
+	    self.unlink(pos);
+	    self.link_front(pos);
+	}
+	Some(pos)
+    }
+
+    /// Is this key in the map. This does not count as a use.
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.contains_key(key)
+    }
+
+    /// Get the value of this key, making it the most recently used entry.
+    #[inline] pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let pos = self.touch(key)?;
+	Some(&self.slot(pos).1.value)
+    }
+
+    /// Get a mutable reference to the value of this key, making it the most recently used entry.
+    #[inline] pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let pos = self.touch(key)?;
+	Some(&mut self.node_mut(pos).value)
+    }
+
+    /// Get the value of this key without using it.
+    #[inline] pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.get(key).map(|node| &node.value)
+    }
+
+    /// Insert an entry, making it the most recently used.
+    ///
+    /// If the key was not present and the map is full, the least recently used entry is evicted.
+    ///
+    /// # Returns
+    /// The replaced entry for the same key if there was one, otherwise the evicted entry if there was one.
+    pub fn push(&mut self, key: K, value: V) -> Option<(K, V)>
+    {
+	if let Some(pos) = self.touch(&key) {
+	    let old = core::mem::replace(&mut self.node_mut(pos).value, value);
+	    return Some((key, old));
+	}
+	self.insert_new(key, value)
+    }
+
+    /// Insert an entry for a key that is not in the map, returning the evicted entry if the map was full.
+    fn insert_new(&mut self, key: K, value: V) -> Option<(K, V)>
+    {
+	let evicted = if self.len >= self.capacity {
+	    self.pop_lru()
+	} else {
+	    None
+	};
+
+	let pos = Pos {
+	    page: self.map.page_for(&key),
+	    slot: key.collapse(),
+	};
+	self.map.0[pos.page].0[usize::from(pos.slot)] = Some((key, Node {
+	    value,
+	    prev: None,
+	    next: None,
+	}));
+	self.len += 1;
+	self.link_front(pos);
+	evicted
+    }
+
+    /// Insert an entry, making it the most recently used, and returning the previous value of the key if it was present.
+    ///
+    /// If the key was not present and the map is full, the least recently used entry is evicted. Use `push()` to get the evicted entry.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	if let Some(pos) = self.touch(&key) {
+	    return Some(core::mem::replace(&mut self.node_mut(pos).value, value));
+	}
+	self.insert_new(key, value);
+	None
+    }
+
+    /// Remove the entry for this key, returning its value if it was present.
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let pos = self.position(key)?;
+	Some(self.take(pos).1)
+    }
+
+    /// Remove all entries
+    pub fn clear(&mut self)
+    {
+	self.map.clear();
+	self.head = None;
+	self.tail = None;
+	self.len = 0;
+    }
+}
+
+impl<K: Debug, V: Debug> Debug for LruMap<K, V>
+{
+    /// Entries are printed from the most to the least recently used.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Collapse, V> core::iter::Extend<(K, V)> for LruMap<K, V>
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I)
+    {
+	for (key, value) in iter.into_iter()
+	{
+	    self.insert(key, value);
+	}
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a LruMap<K, V>
+{
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+
+/// An iterator over the entries of an `LruMap`, from the most to the least recently used.
+pub struct Iter<'a, K, V>
+{
+    map: &'a LruMap<K, V>,
+    next: Option<Pos>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+	let pos = self.next?;
+	let (key, node) = self.map.slot(pos);
+	self.next = node.next;
+	self.remaining -= 1;
+	Some((key, &node.value))
+    }
+
+    #[inline] fn size_hint(&self) -> (usize, Option<usize>)
+    {
+	(self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Iter<'a, K, V>{}
+impl<'a, K, V> core::iter::FusedIterator for Iter<'a, K, V>{}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn order<K: Copy, V>(map: &LruMap<K, V>) -> Vec<K>
+    {
+	map.keys().copied().collect()
+    }
+
+    #[test]
+    fn eviction()
+    {
+	let mut map = LruMap::new(3);
+	map.extend((1..=3).map(|i| (i, i * 10)));
+	assert_eq!(order(&map), vec![3, 2, 1]);
+
+	assert_eq!(map.get(&1), Some(&10));
+	assert_eq!(map.peek(&2), Some(&20));
+	assert_eq!(order(&map), vec![1, 3, 2]);
+
+	assert_eq!(map.push(4, 40), Some((2, 20)));
+	assert_eq!(map.insert(3, 31), Some(30));
+	assert_eq!(order(&map), vec![3, 4, 1]);
+	assert_eq!(map.len(), 3);
+
+	*map.get_mut(&1).unwrap() += 1;
+	assert_eq!(map.peek_lru(), Some((&4, &40)));
+	assert_eq!(map.pop_lru(), Some((4, 40)));
+	assert_eq!(map.remove(&1), Some(11));
+	assert_eq!(order(&map), vec![3]);
+	assert_eq!(map.pop_lru(), Some((3, 31)));
+	assert_eq!(map.pop_lru(), None);
+	assert!(map.is_empty());
+    }
+
+    #[test]
+    fn resize()
+    {
+	let mut map = LruMap::new(600);
+	map.extend((0..600u16).map(|i| (i, ())));
+	assert_eq!(map.len(), 600);
+	assert_eq!(map.map.num_pages(), 3);
+	map.get(&0);
+	map.resize(2);
+	assert_eq!(order(&map), vec![0, 599]);
+	assert_eq!(map.capacity(), 2);
+
+	// Freed slots are reused
+	map.resize(10);
+	map.extend((1000..1008u16).map(|i| (i, ())));
+	assert_eq!(map.len(), 10);
+	assert_eq!(map.map.num_pages(), 3);
+	assert_eq!(map.iter().len(), 10);
+    }
+}
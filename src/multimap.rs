@@ -0,0 +1,353 @@
+//! A map with multiple values per key, built on `Map`.
+//!
+//! The values of each key are stored inline in the map's slot if there are only one or two of them, and are only moved to a heap allocation when a third is inserted (and moved back inline when all but two are removed.)
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let words = ["apple", "avocado", "banana", "blueberry", "cherry"];
+//! let by_letter = MultiMap::group_by(words.iter().copied(), |word| word.as_bytes()[0]);
+//!
+//! assert_eq!(by_letter.get_all(&b'a'), &["apple", "avocado"]);
+//! assert_eq!(by_letter.len_keys(), 3);
+//! assert_eq!(by_letter.len_values(), 5);
+//! ```
+use super::*;
+use core::{
+    convert::TryFrom,
+    fmt::{self, Debug,},
+    mem,
+};
+
+/// The values of a single key. Never empty.
+///
+/// Values are compared and hashed as a slice, so the same values are equal however they are stored.
+#[derive(Clone)]
+enum Values<V>
+{
+    One(V),
+    Two([V; 2]),
+    Many(Vec<V>),
+}
+
+impl<V> Values<V>
+{
+    #[inline] fn as_slice(&self) -> &[V]
+    {
+	match self {
+	    Self::One(v) => core::slice::from_ref(v),
+	    Self::Two(vs) => &vs[..],
+	    Self::Many(vs) => &vs[..],
+	}
+    }
+
+    fn push(&mut self, value: V)
+    {
+	*self = match mem::replace(self, Self::Many(Vec::new())) {
+	    Self::One(a) => Self::Two([a, value]),
+	    Self::Two([a, b]) => Self::Many(vec![a, b, value]),
+	    Self::Many(mut vs) => {
+		vs.push(value);
+		Self::Many(vs)
+	    },
+	};
+    }
+
+    /// Remove the value at this index, which must not be the only value.
+    ///
+    /// If two values are left, they are moved back inline.
+    fn remove(&mut self, index: usize) -> V
+    {
+	let (value, rest) = match mem::replace(self, Self::Many(Vec::new())) {
+	    Self::Two([a, b]) if index == 0 => (a, Self::One(b)),
+	    Self::Two([a, b]) if index == 1 => (b, Self::One(a)),
+	    Self::Many(mut vs) => {
+		let value = vs.remove(index);
+		(value, <[V; 2]>::try_from(vs).map_or_else(Self::Many, Self::Two))
+	    },
+	    _ => panic!("Index {} out of range", index),
+	};
+	*self = rest;
+	value
+    }
+
+    fn into_vec(self) -> Vec<V>
+    {
+	match self {
+	    Self::One(a) => vec![a],
+	    Self::Two([a, b]) => vec![a, b],
+	    Self::Many(vs) => vs,
+	}
+    }
+}
+
+impl<V: PartialEq> PartialEq for Values<V>
+{
+    #[inline] fn eq(&self, other: &Self) -> bool
+    {
+	self.as_slice() == other.as_slice()
+    }
+}
+
+impl<V: Eq> Eq for Values<V>{}
+
+impl<V: Hash> Hash for Values<V>
+{
+    #[inline] fn hash<H: Hasher>(&self, state: &mut H)
+    {
+	self.as_slice().hash(state)
+    }
+}
+
+/// A map that can hold multiple values for each key.
+///
+/// Values are kept in the order they were inserted for each key. A key is present in the map only while it has at least one value.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct MultiMap<K, V>(Map<K, Values<V>>);
+
+impl<K, V> MultiMap<K, V>
+where K: Collapse
+{
+    /// Create a new empty `MultiMap`
+    #[inline] pub fn new() -> Self
+    {
+	Self(Map::new())
+    }
+
+    /// Create a `MultiMap` of the values from `iter`, grouped by the key `key` returns for each.
+    pub fn group_by<I, F>(iter: I, mut key: F) -> Self
+    where I: IntoIterator<Item = V>,
+	  F: FnMut(&V) -> K
+    {
+	let mut this = Self::new();
+	for value in iter
+	{
+	    this.insert(key(&value), value);
+	}
+	this
+    }
+
+    /// The number of distinct keys in the map
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len_keys(&self) -> usize
+    {
+	self.0.len()
+    }
+
+    /// The total number of values of all keys in the map
+    pub fn len_values(&self) -> usize
+    {
+	self.0.values().map(|vs| vs.as_slice().len()).sum()
+    }
+
+    /// Is the map empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.0.is_empty()
+    }
+
+    /// Add a value to this key's values
+    pub fn insert(&mut self, key: K, value: V)
+    {
+	match self.0.entry(key) {
+	    Entry::Occupied(mut entry) => entry.get_mut().push(value),
+	    Entry::Vacant(entry) => {
+		entry.insert(Values::One(value));
+	    },
+	}
+    }
+
+    /// All values of this key, in the order they were inserted. Empty if the key is not in the map.
+    #[inline] pub fn get_all<Q>(&self, key: &Q) -> &[V]
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.get(key).map(Values::as_slice).unwrap_or(&[])
+    }
+
+    /// The first value of this key, if it is in the map
+    #[inline] pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.get_all(key).first()
+    }
+
+    /// Does this key have any values
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.contains_key(key)
+    }
+
+    /// Remove this key and all of its values, returning the values. Empty if the key is not in the map.
+    #[inline] pub fn remove_all<Q>(&mut self, key: &Q) -> Vec<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.remove(key).map(Values::into_vec).unwrap_or_default()
+    }
+
+    /// Remove the first of this key's values that is equal to `value`, returning it if it was present.
+    ///
+    /// If it was the key's only value, the key is removed.
+    pub fn remove_one<Q>(&mut self, key: &Q, value: &V) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq,
+	  V: PartialEq
+    {
+	let values = self.0.get_mut(key)?;
+	let index = values.as_slice().iter().position(|v| v == value)?;
+	if values.as_slice().len() == 1 {
+	    self.0.remove(key).map(|vs| vs.into_vec().remove(0))
+	} else {
+	    Some(values.remove(index))
+	}
+    }
+
+    /// Remove all keys and values
+    #[inline] pub fn clear(&mut self)
+    {
+	self.0.clear()
+    }
+
+    /// An iterator over each key and all of its values
+    #[inline] pub fn iter_all(&self) -> impl Iterator<Item = (&K, &[V])>
+    {
+	self.0.iter().map(|(k, vs)| (k, vs.as_slice()))
+    }
+
+    /// An iterator over every value with its key
+    #[inline] pub fn iter(&self) -> impl Iterator<Item = (&K, &V)>
+    {
+	self.iter_all().flat_map(|(k, vs)| vs.iter().map(move |v| (k, v)))
+    }
+
+    /// An iterator over all keys
+    #[inline] pub fn keys(&self) -> impl Iterator<Item = &K>
+    {
+	self.0.keys()
+    }
+
+    /// An iterator over all values of all keys
+    #[inline] pub fn values(&self) -> impl Iterator<Item = &V>
+    {
+	self.0.values().flat_map(Values::as_slice)
+    }
+}
+
+impl<K: Collapse, V> Default for MultiMap<K, V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+/// Printed as a map of each key to a list of its values.
+impl<K: Debug, V: Debug> Debug for MultiMap<K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries((self.0).0.iter().flat_map(|page| page.0.iter().flatten()).map(|(k, vs)| (k, vs.as_slice()))).finish()
+    }
+}
+
+impl<K: Collapse, V> core::iter::FromIterator<(K, V)> for MultiMap<K, V>
+{
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self
+    {
+	let mut this = Self::new();
+	this.extend(iter);
+	this
+    }
+}
+
+impl<K: Collapse, V> core::iter::Extend<(K, V)> for MultiMap<K, V>
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I)
+    {
+	for (key, value) in iter.into_iter()
+	{
+	    self.insert(key, value);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn values()
+    {
+	let mut map = MultiMap::new();
+	map.insert('a', 1);
+	assert_eq!(map.get_all(&'a'), &[1]);
+	map.insert('a', 2);
+	assert_eq!(map.get_all(&'a'), &[1, 2]);
+	map.insert('a', 3);
+	map.insert('b', 4);
+	assert_eq!(map.get_all(&'a'), &[1, 2, 3]);
+	assert_eq!(map.get(&'b'), Some(&4));
+	assert_eq!(map.get_all(&'z'), &[] as &[i32]);
+	assert_eq!((map.len_keys(), map.len_values()), (2, 4));
+	assert_eq!(format!("{:?}", map), "{'a': [1, 2, 3], 'b': [4]}");
+
+	assert_eq!(map.remove_one(&'a', &2), Some(2));
+	assert_eq!(map.remove_one(&'a', &2), None);
+	assert_eq!(map.get_all(&'a'), &[1, 3]);
+	assert_eq!(map.remove_one(&'b', &4), Some(4));
+	assert!(!map.contains_key(&'b'));
+	assert_eq!(map.remove_all(&'a'), vec![1, 3]);
+	assert_eq!(map.remove_all(&'a'), Vec::<i32>::new());
+	assert!(map.is_empty());
+    }
+
+    #[test]
+    fn inline()
+    {
+	let mut values = Values::One(1);
+	values.push(2);
+	assert!(matches!(values, Values::Two(_)));
+	assert_eq!(values.remove(0), 1);
+	assert!(matches!(values, Values::One(2)));
+	values.push(3);
+	values.push(4);
+	assert_eq!(values.as_slice(), &[2, 3, 4]);
+	assert_eq!(values.remove(1), 3);
+	assert!(matches!(values, Values::Two([2, 4])));
+
+	let mut a: MultiMap<char, i32> = MultiMap::new();
+	a.insert('a', 1);
+	a.insert('a', 2);
+	a.insert('a', 3);
+	let mut b = a.clone();
+	b.remove_one(&'a', &3);
+	let mut c = MultiMap::new();
+	c.insert('a', 1);
+	c.insert('a', 2);
+	assert_eq!(b, c);
+	assert_ne!(a, c);
+
+	let hash = |values: &Values<i32>| {
+	    let mut state = std::collections::hash_map::DefaultHasher::new();
+	    values.hash(&mut state);
+	    state.finish()
+	};
+	assert!(Values::Two([1, 2]) == Values::Many(vec![1, 2]));
+	assert_eq!(hash(&Values::Two([1, 2])), hash(&Values::Many(vec![1, 2])));
+    }
+
+    #[test]
+    fn iteration()
+    {
+	let map: MultiMap<u8, u8> = (0..10).map(|x| (x % 3, x)).collect();
+	assert_eq!(map.iter().count(), 10);
+	assert_eq!(map.values().copied().sum::<u8>(), 45);
+	assert_eq!(map.iter_all().map(|(&k, vs)| (k, vs.len())).collect::<Vec<_>>(), vec![(0, 4), (1, 3), (2, 3)]);
+    }
+}
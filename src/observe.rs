@@ -0,0 +1,477 @@
+//! `Map`s that report their modifications
+//!
+//! An [`ObservedMap`] wraps a `Map` and calls the methods of an [`Observer`] whenever an entry is inserted, replaced, modified or removed through it.
+//! All of `Observer`'s methods do nothing by default, and `()` is an observer that does nothing at all; so an `ObservedMap<K, V, ()>` compiles down to a plain `Map`.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! #[derive(Default)]
+//! struct Count(usize);
+//! impl<K, V> Observer<K, V> for Count
+//! {
+//!     fn on_insert(&mut self, _: &K, _: &V) { self.0 += 1 }
+//!     fn on_remove(&mut self, _: &K, _: &V) { self.0 -= 1 }
+//! }
+//!
+//! let mut map = ObservedMap::new(Count::default());
+//! map.insert('a', 1);
+//! *map.entry('b').or_insert(0) += 1;
+//! map.remove(&'a');
+//! assert_eq!(map.observer().0, 1);
+//! ```
+use super::*;
+use core::ops::{
+    Deref,
+    DerefMut,
+};
+
+/// Callbacks for modifications to an [`ObservedMap`].
+///
+/// Each method is called after the modification has been made. All methods do nothing by default.
+pub trait Observer<K, V>
+{
+    /// A new entry was inserted
+    #[inline(always)] fn on_insert(&mut self, _key: &K, _value: &V){}
+    /// The value of an existing entry was replaced with `new`
+    #[inline(always)] fn on_replace(&mut self, _key: &K, _old: &V, _new: &V){}
+    /// The value of an existing entry was modified in place, and is now `value`
+    #[inline(always)] fn on_modify(&mut self, _key: &K, _value: &V){}
+    /// An entry was removed
+    #[inline(always)] fn on_remove(&mut self, _key: &K, _value: &V){}
+}
+
+/// An observer that does nothing
+impl<K, V> Observer<K, V> for (){}
+
+impl<K, V, O: ?Sized + Observer<K, V>> Observer<K, V> for &mut O
+{
+    #[inline] fn on_insert(&mut self, key: &K, value: &V)
+    {
+	(**self).on_insert(key, value)
+    }
+    #[inline] fn on_replace(&mut self, key: &K, old: &V, new: &V)
+    {
+	(**self).on_replace(key, old, new)
+    }
+    #[inline] fn on_modify(&mut self, key: &K, value: &V)
+    {
+	(**self).on_modify(key, value)
+    }
+    #[inline] fn on_remove(&mut self, key: &K, value: &V)
+    {
+	(**self).on_remove(key, value)
+    }
+}
+
+/// A `Map` that reports modifications made through it to an [`Observer`].
+///
+/// The map can be read through this wrapper (it dereferences to `Map`), but can only be modified through its own methods.
+/// Values returned by its entries (e.g. from [`ObservedEntry::or_insert()`]) are [`ObservedValue`]s, which report the entry with its final value when they are dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ObservedMap<K, V, O = ()>
+{
+    map: Map<K,V>,
+    observer: O,
+}
+
+impl<K, V, O> ObservedMap<K, V, O>
+where K: Collapse,
+      O: Observer<K, V>
+{
+    /// Create a new empty map that reports to `observer`
+    #[inline] pub fn new(observer: O) -> Self
+    {
+	Self::from_map(Map::new(), observer)
+    }
+
+    /// Wrap an existing map. Its existing entries are not reported to `observer`.
+    #[inline] pub fn from_map(map: Map<K,V>, observer: O) -> Self
+    {
+	Self { map, observer }
+    }
+
+    /// A reference to the observer
+    #[inline] pub fn observer(&self) -> &O
+    {
+	&self.observer
+    }
+
+    /// A mutable reference to the observer
+    #[inline] pub fn observer_mut(&mut self) -> &mut O
+    {
+	&mut self.observer
+    }
+
+    /// Mutable references to the map and the observer, for modifying the map without reporting it.
+    #[inline] pub(crate) fn parts_mut(&mut self) -> (&mut Map<K,V>, &mut O)
+    {
+	(&mut self.map, &mut self.observer)
+    }
+
+    /// Consume this wrapper, returning the map and the observer
+    #[inline] pub fn into_parts(self) -> (Map<K,V>, O)
+    {
+	(self.map, self.observer)
+    }
+
+    /// Consume this wrapper, returning the map
+    #[inline] pub fn into_map(self) -> Map<K,V>
+    {
+	self.map
+    }
+
+    /// Insert a new key-value entry into this map, returning the pervious value if it was present
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	self.entry(key).insert(value)
+    }
+
+    /// Get an `ObservedEntry` for the `key` that lets you get, insert, modify or remove its value
+    pub fn entry(&mut self, key: K) -> ObservedEntry<'_, K, V, O>
+    {
+	let page = self.map.page_for(&key);
+	let slot = self.map.0[page].search_mut(&key);
+	let key = if slot.is_some() { None } else { Some(key) };
+	ObservedEntry {
+	    slot,
+	    key,
+	    observer: &mut self.observer,
+	}
+    }
+
+    /// Modify the value corresponding to this key in place, if it is in the map.
+    ///
+    /// Returns the result of `f`, or `None` if the key is not in the map.
+    pub fn modify<Q, F, T>(&mut self, key: &Q, f: F) -> Option<T>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq,
+	  F: FnOnce(&mut V) -> T
+    {
+	let (k, v) = self.map.0.iter_mut()
+	    .map(|page| page.search_mut(key))
+	    .find_map(|slot| slot.as_mut().filter(|(k, _)| key.eq(k.borrow())))?;
+	let output = f(v);
+	self.observer.on_modify(k, v);
+	Some(output)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the value if it was present
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the key-value pair if it was present
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (k, v) = self.map.remove_entry(key)?;
+	self.observer.on_remove(&k, &v);
+	Some((k, v))
+    }
+
+    /// Keep only the entries for which `keep` returns `true`, removing all others.
+    ///
+    /// Since `keep` can modify the values of entries it keeps, each kept entry is reported as modified.
+    pub fn retain<F>(&mut self, mut keep: F)
+    where F: FnMut(&K, &mut V) -> bool
+    {
+	let observer = &mut self.observer;
+	for slot in self.map.0.iter_mut().flat_map(|page| page.0.iter_mut())
+	{
+	    if let Some((k, v)) = slot {
+		if keep(k, v) {
+		    observer.on_modify(k, v);
+		} else {
+		    observer.on_remove(k, v);
+		    *slot = None;
+		}
+	    }
+	}
+    }
+
+    /// Remove all entries from this map, reporting each one as removed.
+    pub fn clear(&mut self)
+    {
+	self.retain(|_, _| false)
+    }
+}
+
+impl<K, V, O> Default for ObservedMap<K, V, O>
+where K: Collapse,
+      O: Observer<K, V> + Default
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new(O::default())
+    }
+}
+
+impl<K, V, O> Deref for ObservedMap<K, V, O>
+{
+    type Target = Map<K,V>;
+
+    #[inline] fn deref(&self) -> &Self::Target
+    {
+	&self.map
+    }
+}
+
+impl<K, V, O> core::iter::Extend<(K,V)> for ObservedMap<K, V, O>
+where K: Collapse,
+      O: Observer<K, V>
+{
+    fn extend<T: IntoIterator<Item = (K,V)>>(&mut self, iter: T)
+    {
+	for (key, value) in iter.into_iter()
+	{
+	    self.insert(key, value);
+	}
+    }
+}
+
+/// A slot in an [`ObservedMap`] that may or may not contain an entry for a key.
+///
+/// See [`ObservedMap::entry()`].
+pub struct ObservedEntry<'a, K, V, O>
+{
+    slot: &'a mut Option<(K,V)>,
+    /// The key, if the slot is vacant
+    key: Option<K>,
+    observer: &'a mut O,
+}
+
+impl<'a, K, V, O> ObservedEntry<'a, K, V, O>
+where K: Collapse,
+      O: Observer<K, V>
+{
+    /// A reference to the key
+    pub fn key(&self) -> &K
+    {
+	match (&self.key, &*self.slot) {
+	    (Some(key), _) | (None, Some((key, _))) => key,
+	    (None, None) => unreachable!("Entry has no key"),
+	}
+    }
+
+    /// A reference to the value, if the entry is occupied
+    #[inline] pub fn get(&self) -> Option<&V>
+    {
+	self.slot.as_ref().map(|(_, v)| v)
+    }
+
+    /// Is there a value for this key
+    #[inline] pub fn is_occupied(&self) -> bool
+    {
+	self.slot.is_some()
+    }
+
+    /// Insert a value into this entry, returning the previous value if it was occupied.
+    pub fn insert(mut self, value: V) -> Option<V>
+    {
+	match (self.key.take(), &mut *self.slot) {
+	    (None, Some((k, v))) => {
+		let old = core::mem::replace(v, value);
+		self.observer.on_replace(k, &old, v);
+		Some(old)
+	    },
+	    (Some(key), slot) => {
+		let (k, v) = slot.insert((key, value));
+		self.observer.on_insert(k, v);
+		None
+	    },
+	    (None, None) => unreachable!("Entry has no key"),
+	}
+    }
+
+    /// Remove the entry if it is occupied, returning its value.
+    pub fn remove(self) -> Option<V>
+    {
+	let (k, v) = self.slot.take()?;
+	self.observer.on_remove(&k, &v);
+	Some(v)
+    }
+
+    /// Run this closure on a mutable reference to the value if it is present, reporting it as modified, otherwise do nothing.
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self
+    {
+	if let Some((k, v)) = self.slot {
+	    f(v);
+	    self.observer.on_modify(k, v);
+	}
+	self
+    }
+
+    /// Insert into the entry if it is empty the value returned by the closure and return a mutable reference to the new value, otherwise return a mutable reference to the already present value.
+    ///
+    /// The insertion, or any modification made through the returned value, is reported when it is dropped.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, with: F) -> ObservedValue<'a, K, V, O>
+    {
+	let inserted = self.key.is_some();
+	let entry = match (self.key, self.slot) {
+	    (Some(key), slot) => slot.insert((key, with())),
+	    (None, Some(entry)) => entry,
+	    (None, None) => unreachable!("Entry has no key"),
+	};
+	ObservedValue {
+	    entry,
+	    observer: self.observer,
+	    inserted,
+	    modified: false,
+	}
+    }
+
+    /// Insert into the entry this value if it is empty and return a mutable reference to the new value, otherwise return a mutable reference to the already present value.
+    ///
+    /// The insertion, or any modification made through the returned value, is reported when it is dropped.
+    #[inline] pub fn or_insert(self, value: V) -> ObservedValue<'a, K, V, O>
+    {
+	self.or_insert_with(|| value)
+    }
+
+    /// Insert into the entry the default value if it is empty and return a mutable reference to the new value, otherwise return a mutable reference to the already present value.
+    ///
+    /// The insertion, or any modification made through the returned value, is reported when it is dropped.
+    #[inline] pub fn or_default(self) -> ObservedValue<'a, K, V, O>
+    where V: Default
+    {
+	self.or_insert_with(Default::default)
+    }
+}
+
+/// A mutable reference to the value of an entry in an [`ObservedMap`].
+///
+/// When this is dropped, the entry is reported with its final value: as inserted if it was vacant before, or as modified if it was mutably dereferenced.
+///
+/// See [`ObservedEntry::or_insert()`].
+pub struct ObservedValue<'a, K, V, O>
+where O: Observer<K, V>
+{
+    entry: &'a mut (K,V),
+    observer: &'a mut O,
+    /// Was the entry vacant before
+    inserted: bool,
+    /// Has the value been mutably dereferenced
+    modified: bool,
+}
+
+impl<'a, K, V, O> ObservedValue<'a, K, V, O>
+where O: Observer<K, V>
+{
+    /// A reference to the key
+    #[inline] pub fn key(&self) -> &K
+    {
+	&self.entry.0
+    }
+}
+
+impl<'a, K, V, O> Deref for ObservedValue<'a, K, V, O>
+where O: Observer<K, V>
+{
+    type Target = V;
+
+    #[inline] fn deref(&self) -> &Self::Target
+    {
+	&self.entry.1
+    }
+}
+
+impl<'a, K, V, O> DerefMut for ObservedValue<'a, K, V, O>
+where O: Observer<K, V>
+{
+    #[inline] fn deref_mut(&mut self) -> &mut Self::Target
+    {
+	self.modified = true;
+	&mut self.entry.1
+    }
+}
+
+impl<'a, K, V, O> Drop for ObservedValue<'a, K, V, O>
+where O: Observer<K, V>
+{
+    fn drop(&mut self)
+    {
+	let (k, v) = &*self.entry;
+	if self.inserted {
+	    self.observer.on_insert(k, v);
+	} else if self.modified {
+	    self.observer.on_modify(k, v);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::string::String;
+
+    #[derive(Default)]
+    struct Log(Vec<String>);
+
+    impl Observer<char, i32> for Log
+    {
+	fn on_insert(&mut self, key: &char, value: &i32)
+	{
+	    self.0.push(format!("+{}={}", key, value));
+	}
+	fn on_replace(&mut self, key: &char, old: &i32, new: &i32)
+	{
+	    self.0.push(format!("{}:{}->{}", key, old, new));
+	}
+	fn on_modify(&mut self, key: &char, value: &i32)
+	{
+	    self.0.push(format!("~{}={}", key, value));
+	}
+	fn on_remove(&mut self, key: &char, value: &i32)
+	{
+	    self.0.push(format!("-{}={}", key, value));
+	}
+    }
+
+    #[test]
+    fn observed()
+    {
+	let mut map = ObservedMap::new(Log::default());
+	map.insert('a', 1);
+	map.insert('a', 2);
+	map.extend(vec![('b', 3), ('c', 4)]);
+	*map.entry('d').or_default() += 5;
+	map.entry('b').and_modify(|v| *v *= 10).or_insert(0);
+	map.entry('b').or_insert(0);
+	{
+	    let mut value = map.entry('e').or_insert(1);
+	    *value += 1;
+	    *value *= 3;
+	}
+	map.entry('e').or_insert(0);
+	*map.entry('e').or_insert(0) -= 1;
+	assert_eq!(map.modify(&'c', |v| *v += 1), Some(()));
+	assert_eq!(map.modify(&'z', |v| *v += 1), None);
+	assert_eq!(map.remove(&'a'), Some(2));
+	map.retain(|&k, v| {
+	    *v += 1;
+	    k != 'd' && k != 'e'
+	});
+	map.clear();
+
+	let (map, Log(log)) = map.into_parts();
+	assert!(map.is_empty());
+	assert_eq!(log, ["+a=1", "a:1->2", "+b=3", "+c=4", "+d=5", "~b=30", "+e=6", "~e=5", "~c=5", "-a=2", "~b=31", "~c=6", "-d=6", "-e=6", "-b=31", "-c=6"]);
+    }
+
+    #[test]
+    fn no_op()
+    {
+	assert_eq!(core::mem::size_of::<ObservedMap<u8, u8>>(), core::mem::size_of::<Map<u8, u8>>());
+
+	let mut map: ObservedMap<u8, u8> = ObservedMap::default();
+	map.insert(1, 2);
+	assert_eq!(map.get(&1), Some(&2));
+	assert_eq!(map.into_map(), smallmap!{ {1 => 2} });
+    }
+}
@@ -22,11 +22,12 @@ impl<K: Clone, V: Clone> Clone for Page<K,V>
     }
 }
 
+/// Only occupied slots are printed, as a map of `{slot: (key, value), ...}`.
 impl<K: Debug, V:Debug> Debug for Page<K,V>
 {
-    #[inline] fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
     {
-	write!(f, "{:?}", &self.0[..])
+	f.debug_map().entries(self.0.iter().enumerate().filter_map(|(i, slot)| slot.as_ref().map(|kv| (i, kv)))).finish()
     }
 }
 
@@ -36,13 +37,13 @@ impl<K: PartialEq, V: PartialEq> PartialEq for Page<K,V>
 {
     #[inline] fn eq(&self, other: &Self) -> bool
     {
-	&self.0[..] == &other.0[..]
+	self.0[..] == other.0[..]
     }
 }
 
 impl<K: hash::Hash, V: hash::Hash> hash::Hash for Page<K,V> {
     #[inline] fn hash<H: hash::Hasher>(&self, state: &mut H) {
-	(&self.0[..]).hash(state)
+	self.0[..].hash(state)
     }
 }
 
@@ -51,6 +52,7 @@ const _: () = {
     use serde::*;
     use core::marker::PhantomData;
     
+    /// Pages are serialised compactly as a map of only the occupied slots to their entries: `{slot: (key, value), ...}`.
     impl<K,V> serde::Serialize for Page<K,V>
     where K:Serialize, V: Serialize
     {
@@ -58,56 +60,95 @@ const _: () = {
 	where
             S: Serializer,
 	{
-	    use serde::ser::SerializeSeq;
-	    let mut seq = serializer.serialize_seq(Some(MAX))?;
-            for element in self.0.iter() {
-		seq.serialize_element(element)?;
+	    use serde::ser::SerializeMap;
+	    let mut map = serializer.serialize_map(Some(self.0.iter().flatten().count()))?;
+            for (slot, element) in (0..=u8::MAX).zip(self.0.iter()) {
+		if let Some((k, v)) = element {
+		    map.serialize_entry(&slot, &(k, v))?;
+		}
             }
-            seq.end()
+            map.end()
 	}
     }
 
     struct PageVisitor<K,V>(PhantomData<Page<K,V>>);
+
+    impl<K: Collapse, V> PageVisitor<K,V>
+    {
+	fn validate<E: de::Error>(page: Page<K,V>) -> Result<Page<K,V>, E>
+	{
+	    match page.find_misplaced() {
+		Some((slot, expected)) => Err(E::custom(alloc::format!("Entry in slot {} has a key that collapses to slot {}", slot, expected))),
+		None => Ok(page),
+	    }
+	}
+    }
     
     impl<'de, K, V> de::Visitor<'de> for PageVisitor<K,V> 
-    where K: Deserialize<'de>,
+    where K: Collapse + Deserialize<'de>,
 	  V: Deserialize<'de>
     {
 	type Value = Page<K,V>;
 
 	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("an array of 256 `Option<(K,V)>` elements")
+            formatter.write_str("a map of slot indecies to `(K,V)` entries, or an array of 256 `Option<(K,V)>` elements")
+	}
+
+	fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where
+	    A: de::MapAccess<'de>
+	{
+	    use serde::de::Error;
+	    let mut elems = init::blank_page();
+	    while let Some((slot, kv)) = map.next_entry::<u8, (K,V)>()?
+	    {
+		let elem = &mut elems[usize::from(slot)];
+		if elem.is_some() {
+		    return Err(A::Error::custom(alloc::format!("Duplicate entry for slot {}", slot)));
+		}
+		*elem = Some(kv);
+	    }
+	    Self::validate(Page(elems))
 	}
 
 	fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where
-	    A: serde::de::SeqAccess<'de>
+	    A: de::SeqAccess<'de>
 	{
+	    use serde::de::Error;
 	    let mut elems = init::blank_page();
 	    let mut i=0usize;
 	    while let Some(optkv) = seq.next_element()?
 	    {
-		elems[i] = optkv;
+		match elems.get_mut(i) {
+		    Some(elem) => *elem = optkv,
+		    None => return Err(A::Error::invalid_length(i + 1, &self)),
+		}
 		i+=1;
 	    }
 	    if i==MAX {
-		Ok(Page(elems))
+		Self::validate(Page(elems))
 	    } else {
-		use serde::de::Error;
 		Err(A::Error::custom(alloc::format!("Expected {} elemts, got {}", MAX, i)))
 	    }
 	}
     }
 
     
+    /// Both the compact map form and the legacy form of all 256 `Option<(K,V)>` slots are accepted, although the legacy form can only be read from self-describing formats.
+    ///
+    /// The deserialised page is validated, and an error is returned if any entry's key does not collapse to the slot it is in.
     impl<'de, K, V> Deserialize<'de> for Page<K,V>
-    where K: Deserialize<'de>,
+    where K: Collapse + Deserialize<'de>,
 	  V: Deserialize<'de>
     {
 	fn deserialize<D>(deserializer: D) -> Result<Page<K,V>, D::Error>
 	where
             D: serde::de::Deserializer<'de>,
 	{
-            deserializer.deserialize_seq(PageVisitor(PhantomData))
+	    if deserializer.is_human_readable() {
+		deserializer.deserialize_any(PageVisitor(PhantomData))
+	    } else {
+		deserializer.deserialize_map(PageVisitor(PhantomData))
+	    }
 	}
     }
 
@@ -118,7 +159,6 @@ const _: () = {
 #[cfg(test)]
 mod serde_tests
 {
-    use crate::*;
     #[test]
     fn serde()
     {
@@ -128,7 +168,59 @@ mod serde_tests
 	};
 	let string = serde_json::to_string(&map).expect("ser failed");
 	println!("String {:?}", string);
-	let map2 = serde_json::from_str(&string[..]).expect("de failed");
+	// `Map` can also be compared with `HashMap` and `BTreeMap`, so the type has to be given
+	let map2: crate::Map<_, _> = serde_json::from_str(&string[..]).expect("de failed");
 	assert_eq!(map, map2);
     }
+
+    #[test]
+    fn serde_page_validated()
+    {
+	let mut page = crate::Page::<u8, u8>::new();
+	page.replace(5, 6);
+	let mut string = serde_json::to_string(&page).expect("ser failed");
+	let page2: crate::Page<u8, u8> = serde_json::from_str(&string[..]).expect("de failed");
+	assert_eq!(page, page2);
+
+	string = string.replace("[5,6]", "[7,6]");
+	assert!(serde_json::from_str::<crate::Page<u8, u8>>(&string[..]).is_err());
+    }
+
+    #[test]
+    fn serde_page_compact()
+    {
+	let mut page = crate::Page::<u8, char>::new();
+	page.replace(5, 'a');
+	page.replace(200, 'b');
+	let string = serde_json::to_string(&page).expect("ser failed");
+	assert_eq!(string, r#"{"5":[5,"a"],"200":[200,"b"]}"#);
+
+	assert!(serde_json::from_str::<crate::Page<u8, char>>(r#"{"5":[5,"a"],"5":[5,"b"]}"#).is_err());
+    }
+
+    #[test]
+    fn serde_page_legacy()
+    {
+	let mut page = crate::Page::<u8, char>::new();
+	page.replace(5, 'a');
+	let legacy = page.0.to_vec();
+	let string = serde_json::to_string(&legacy).expect("ser failed");
+	let page2: crate::Page<u8, char> = serde_json::from_str(&string[..]).expect("de failed");
+	assert_eq!(page, page2);
+
+	let too_long = serde_json::to_string(&vec![None::<(u8, char)>; crate::MAX + 1]).expect("ser failed");
+	assert!(serde_json::from_str::<crate::Page<u8, char>>(&too_long[..]).is_err());
+    }
+
+    #[test]
+    fn serde_capacity()
+    {
+	use serde::Deserialize;
+	use serde::de::value::{MapDeserializer, Error};
+	// The size hint is a number of entries, not pages
+	let entries = MapDeserializer::<_, Error>::new((0..300u16).map(|i| (i, i)));
+	let map = crate::Map::<u16, u16>::deserialize(entries).expect("de failed");
+	assert_eq!(map.len(), 300);
+	assert_eq!(map.num_pages(), 2);
+    }
 }
@@ -0,0 +1,484 @@
+//! A `Map` with structurally shared pages
+//!
+//! Cloning a `Map` copies every one of its pages. A [`PersistentMap`] instead keeps its pages behind `Arc`s, so cloning it only copies a pointer per page, and each page is copied only when it is first modified through a clone that shares it.
+//! This makes it cheap to keep many snapshots of a map that is only changed a little between them (e.g. for an undo stack.)
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut map: PersistentMap<u16, char> = (0..600).map(|x| (x, 'a')).collect();
+//! let snapshot = map.clone();
+//!
+//! map.insert(1, 'b');
+//! // Only the page containing the modified entry has been copied
+//! assert_eq!(map.shared_pages(&snapshot), map.num_pages() - 1);
+//! assert_eq!(snapshot.get(&1), Some(&'a'));
+//! assert_eq!(map.get(&1), Some(&'b'));
+//! ```
+use super::*;
+use alloc::sync::Arc;
+use core::{
+    fmt,
+    iter::FusedIterator,
+};
+
+/// A small hashtable-like map with byte sized key indecies, whose pages are shared between clones and copied on write.
+///
+/// The read API is the same as [`Map`]'s. Modifying the map requires `K: Clone` and `V: Clone`, since a page that is shared with another clone must be copied before it is modified.
+///
+/// Like `Map`s, maps are equal (and hash the same) if they have the same entries, regardless of which pages the entries are in.
+pub struct PersistentMap<K, V>(Vec<Arc<Page<K,V>>>);
+
+impl<K, V> Clone for PersistentMap<K,V>
+{
+    /// Clone the map by sharing all of its pages
+    #[inline] fn clone(&self) -> Self
+    {
+	Self(self.0.clone())
+    }
+}
+
+impl<K: PartialEq, V: PartialEq> PartialEq for PersistentMap<K,V>
+{
+    /// Do both maps have the same entries
+    #[inline] fn eq(&self, other: &Self) -> bool
+    {
+	pages_eq(&self.0[..], &other.0[..])
+    }
+}
+
+impl<K: Eq, V: Eq> Eq for PersistentMap<K,V>{}
+
+impl<K: Hash, V: Hash> Hash for PersistentMap<K,V>
+{
+    #[inline] fn hash<H: Hasher>(&self, state: &mut H)
+    {
+	hash_pages(&self.0[..], state)
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for PersistentMap<K,V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries(self.0.iter().flat_map(|page| page.0.iter().flatten()).map(|(k, v)| (k, v))).finish()
+    }
+}
+
+impl<K,V> PersistentMap<K,V>
+where K: Collapse
+{
+    /// Create a new empty `PersistentMap`
+    pub fn new() -> Self
+    {
+	Self(vec![Arc::new(Page::new())])
+    }
+
+    /// Create a new empty `PersistentMap` with space for a specific number of pages pre-allocated
+    pub fn with_capacity(pages: usize) -> Self
+    {
+	#[cold] fn cap_too_low() -> !
+	{
+	    panic!("Got 0 capacity, this is invalid.")
+	}
+
+	if pages == 0 {
+	    cap_too_low()
+	}
+	let mut p = Vec::with_capacity(pages);
+	p.push(Arc::new(Page::new()));
+	Self(p)
+    }
+
+    /// The number of entries currently in this map
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    pub fn len(&self) -> usize
+    {
+	self.pages().map(Page::len).sum()
+    }
+
+    /// Is this map empty
+    pub fn is_empty(&self) -> bool
+    {
+	self.pages().all(Page::is_empty)
+    }
+
+    /// The number of pages currently in this map
+    #[inline] pub fn num_pages(&self) -> usize
+    {
+	self.0.len()
+    }
+
+    /// The number of pages that this map shares with `other`
+    ///
+    /// Pages are shared between clones until they are modified through one of them.
+    pub fn shared_pages(&self, other: &Self) -> usize
+    {
+	self.0.iter().filter(|page| other.0.iter().any(|other| Arc::ptr_eq(page, other))).count()
+    }
+
+    /// An iterator over all pages
+    #[inline] pub fn pages(&self) -> Pages<'_, K, V>
+    {
+	Pages(self.0.iter())
+    }
+
+    /// An iterator over all elements in the map
+    #[inline] pub fn iter(&self) -> Iter<'_, K, V>
+    {
+	Iter(None, self.pages())
+    }
+
+    /// An iterator over all the keys in the map
+    pub fn keys(&self) -> impl Iterator<Item = &K>
+    {
+	self.iter().map(|(k, _)| k)
+    }
+
+    /// An iterator over all the values in the map
+    pub fn values(&self) -> impl Iterator<Item = &V>
+    {
+	self.iter().map(|(_, v)| v)
+    }
+
+    /// Find the entry corresponding to this key, and the index of the page it is in
+    fn find<Q>(&self, key: &Q) -> Option<(usize, &(K, V))>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.iter().enumerate().find_map(|(i, page)| match page.search(key) {
+	    Some(kv) if key.eq(kv.0.borrow()) => Some((i, kv)),
+	    _ => None,
+	})
+    }
+
+    /// Search the map for entry corresponding to this key
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.find(key).is_some()
+    }
+
+    /// Get a reference of the value corresponding to this key if it is in the map.
+    #[inline] pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.find(key).map(|(_, (_, v))| v)
+    }
+
+    /// Get a reference to the key and the value corresponding to this key if it is in the map.
+    #[inline] pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.find(key).map(|(_, (k, v))| (k, v))
+    }
+}
+
+impl<K,V> PersistentMap<K,V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    /// Get a mutable reference to a page, copying it first if it is shared.
+    #[inline] fn page_mut(&mut self, page: usize) -> &mut Page<K,V>
+    {
+	Arc::make_mut(&mut self.0[page])
+    }
+
+    /// Get a mutable reference of the value corresponding to this key if it is in the map.
+    ///
+    /// If the page containing the entry is shared, it is copied.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (page, _) = self.find(key)?;
+	self.page_mut(page).search_mut(key).as_mut().map(|(_, v)| v)
+    }
+
+    /// Insert a new key-value entry into this map, returning the pervious value if it was present
+    ///
+    /// Only the page the entry is inserted into is copied, if it is shared.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	// The key may be in a later page than the first one with a hole in its slot
+	let page = self.find(&key).map(|(page, _)| page)
+	    .or_else(|| self.0.iter().position(|page| page.search(&key).is_none()));
+	match page {
+	    Some(page) => self.page_mut(page).replace(key, value).map(|(_, v)| v),
+	    None => {
+		let mut page = Page::new();
+		page.replace(key, value);
+		self.0.push(Arc::new(page));
+		None
+	    },
+	}
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the value if it was present
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the key-value pair if it was present
+    ///
+    /// Only the page the entry is removed from is copied, if it is shared.
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let (page, _) = self.find(key)?;
+	self.page_mut(page).search_mut(key).take()
+    }
+
+    /// Consume this map into a `Map`, copying only the pages that are still shared.
+    pub fn into_map(self) -> Map<K,V>
+    {
+	Map(self.0.into_iter().map(|page| Arc::try_unwrap(page).unwrap_or_else(|page| Page::clone(&page))).collect())
+    }
+}
+
+impl<K,V> PersistentMap<K,V>
+where K: Collapse
+{
+    /// Remove all entries from this map.
+    ///
+    /// Unlike [`Map::clear()`](Map::clear), the pages are not kept (since they may be shared), and the map is left with a single new blank page.
+    pub fn clear(&mut self)
+    {
+	self.0.clear();
+	self.0.push(Arc::new(Page::new()));
+    }
+
+    /// Remove all empty pages from this instance.
+    ///
+    /// If all pages are empty, a single blank page is kept.
+    pub fn clean(&mut self)
+    {
+	self.0.retain(|x| !x.is_empty());
+	if self.0.is_empty() {
+	    self.0.push(Arc::new(Page::new()));
+	}
+    }
+}
+
+impl<K: Collapse, V> Default for PersistentMap<K,V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<K: Collapse, V> From<Map<K,V>> for PersistentMap<K,V>
+{
+    /// Move each of the map's pages behind an `Arc`
+    #[inline] fn from(from: Map<K,V>) -> Self
+    {
+	Self(from.0.into_iter().map(Arc::new).collect())
+    }
+}
+
+impl<K: Collapse + Clone, V: Clone> From<PersistentMap<K,V>> for Map<K,V>
+{
+    #[inline] fn from(from: PersistentMap<K,V>) -> Self
+    {
+	from.into_map()
+    }
+}
+
+impl<K: Collapse, V> core::iter::FromIterator<(K, V)> for PersistentMap<K,V>
+{
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self
+    {
+	Map::from_iter(iter).into()
+    }
+}
+
+impl<K: Collapse + Clone, V: Clone> core::iter::Extend<(K,V)> for PersistentMap<K,V>
+{
+    fn extend<T: IntoIterator<Item = (K,V)>>(&mut self, iter: T)
+    {
+	for (key, value) in iter.into_iter()
+	{
+	    self.insert(key, value);
+	}
+    }
+}
+
+impl<'a, K: Collapse, V> IntoIterator for &'a PersistentMap<K,V>
+{
+    type Item = &'a (K,V);
+    type IntoIter = Iter<'a, K, V>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+
+impl<K, Q, V> core::ops::Index<&Q> for PersistentMap<K, V>
+where K: Collapse + Borrow<Q>,
+      Q: ?Sized + Collapse + Eq,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &Self::Output
+    {
+	self.get(key).expect("Key not found")
+    }
+}
+
+/// An iterator over the `Page`s of a [`PersistentMap`]
+pub struct Pages<'a, K, V>(core::slice::Iter<'a, Arc<Page<K,V>>>);
+
+impl<'a, K, V> Iterator for Pages<'a, K, V>
+{
+    type Item = &'a Page<K,V>;
+
+    #[inline] fn next(&mut self) -> Option<Self::Item>
+    {
+	self.0.next().map(|page| &**page)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>)
+    {
+	self.0.size_hint()
+    }
+}
+
+impl<'a, K, V> ExactSizeIterator for Pages<'a, K, V>{}
+impl<'a, K, V> FusedIterator for Pages<'a, K, V>{}
+
+/// An iterator over the elements of a [`PersistentMap`]
+pub struct Iter<'a, K, V>(Option<PageElements<'a, K, V>>, Pages<'a, K, V>);
+
+impl<'a, K, V> Iterator for Iter<'a, K, V>
+where K: Collapse
+{
+    type Item = &'a (K,V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+	loop {
+	    if let Some(next) = self.0.as_mut().and_then(Iterator::next) {
+		return Some(next);
+	    }
+	    self.0 = Some(self.1.next()?.iter());
+	}
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>)
+    {
+	(0, self.1.size_hint().1.map(|x| x * MAX))
+    }
+}
+
+impl<'a, K: Collapse, V> FusedIterator for Iter<'a, K, V>{}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn copy_on_write()
+    {
+	let mut map: PersistentMap<u16, u16> = (0..1000).map(|x| (x, x)).collect();
+	assert_eq!(map.num_pages(), 4);
+	let snapshot = map.clone();
+	assert_eq!(map.shared_pages(&snapshot), 4);
+
+	*map.get_mut(&1).unwrap() = 10;
+	assert_eq!(map.remove(&2), Some(2));
+	assert_eq!(map.shared_pages(&snapshot), 3);
+
+	assert_eq!(map.insert(300, 30), Some(300));
+	assert_eq!(map.shared_pages(&snapshot), 2);
+
+	assert_eq!(map.insert(2, 2), None);
+	assert_eq!(map.shared_pages(&snapshot), 2);
+
+	assert_eq!(snapshot.get(&1), Some(&1));
+	assert_eq!(snapshot.get(&300), Some(&300));
+	assert_eq!(map.get(&1), Some(&10));
+	assert_eq!(map[&300], 30);
+	assert_eq!(map.len(), 1000);
+    }
+
+    #[test]
+    fn reinsert_after_remove()
+    {
+	let mut map: PersistentMap<u16, u16> = PersistentMap::new();
+	map.insert(1, 1);
+	map.insert(257, 257);
+	let snapshot = map.clone();
+	assert_eq!(map.remove(&1), Some(1));
+	assert!(!map.is_empty());
+	assert_eq!(map.insert(257, 0), Some(257));
+	assert_eq!(map.len(), 1);
+	assert_eq!(map.remove(&257), Some(0));
+	assert!(map.is_empty());
+	assert_eq!(snapshot.len(), 2);
+    }
+
+    #[test]
+    fn layout_independent()
+    {
+	let mut a: PersistentMap<u16, u16> = PersistentMap::new();
+	a.insert(1, 1);
+	a.insert(257, 2);
+	let mut b: PersistentMap<u16, u16> = PersistentMap::new();
+	b.insert(257, 2);
+	b.insert(1, 1);
+	assert_eq!(a, b);
+
+	let hash = |map: &PersistentMap<u16, u16>| {
+	    let mut state = std::collections::hash_map::DefaultHasher::new();
+	    map.hash(&mut state);
+	    state.finish()
+	};
+	assert_eq!(hash(&a), hash(&b));
+	b.insert(1, 3);
+	assert_ne!(a, b);
+    }
+
+    #[test]
+    fn conversions()
+    {
+	let map: Map<char, usize> = "hello world".chars().zip(0..).collect();
+	let persistent = PersistentMap::from(map.clone());
+	assert_eq!(persistent.len(), map.len());
+	assert!(persistent.iter().eq(map.iter()));
+	assert_eq!(format!("{:?}", persistent), format!("{:?}", map));
+
+	let snapshot = persistent.clone();
+	assert_eq!(Map::from(persistent), map);
+	assert_eq!(snapshot.into_map(), map);
+    }
+
+    #[test]
+    fn clear_clean()
+    {
+	let mut map: PersistentMap<u16, ()> = (0..600).map(|x| (x, ())).collect();
+	let snapshot = map.clone();
+	map.clear();
+	assert!(map.is_empty());
+	assert_eq!(map.num_pages(), 1);
+	assert_eq!(snapshot.len(), 600);
+
+	let mut map = snapshot;
+	for x in 256..600 {
+	    map.remove(&x);
+	}
+	map.clean();
+	assert_eq!(map.num_pages(), 1);
+	assert_eq!(map.len(), 256);
+    }
+}
@@ -35,6 +35,7 @@ pub trait PrimitiveCollapse: private::Sealed
 /// This code pretty much only works with `u8`. and `i8`.
 ///
 /// However unsafe, it is possible these values will line up in your use case. In which case, it is an acceptable pattern.
+/// [`Map::validate()`](crate::Map::validate) can be used to check if they do, and [`Map::repair()`](crate::Map::repair) to move the entries to where they should be if they don't.
 #[derive(Debug, Clone, PartialEq, Eq, Copy, Default, Ord, PartialOrd)]
 #[repr(transparent)]
 pub struct Primitive<T>(T);
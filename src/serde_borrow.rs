@@ -0,0 +1,254 @@
+//! Zero-copy deserialisation of `Map`s with borrowed keys
+//!
+//! This is used with `#[serde(with = "smallmap::serde_borrow")]` on fields of `Map` type.
+//!
+//! `Map`'s own `Deserialize` impl already supports keys of `&'de str` and `&'de [u8]`, but `Cow<'de, str>` and `Cow<'de, [u8]>` keys are always deserialised as owned.
+//! With this adaptor, `Cow` keys borrow from the input whenever the format allows it (e.g. a JSON string key without escape sequences), and only allocate when it doesn't.
+//! The serialised representation is the same as `Map`'s.
+//!
+//! # Usage
+//! ```
+//! # use smallmap::*;
+//! # use std::borrow::Cow;
+//! #[derive(serde::Deserialize)]
+//! struct Table<'a>
+//! {
+//!     #[serde(borrow, with = "smallmap::serde_borrow")]
+//!     map: Map<Cow<'a, str>, i32>,
+//! }
+//! let json = r#"{"map": {"plain": 1, "escaped\n": 2}}"#;
+//! let table: Table = serde_json::from_str(json).unwrap();
+//!
+//! assert!(matches!(table.map.keys().find(|k| k == &"plain"), Some(Cow::Borrowed(_))));
+//! assert!(matches!(table.map.keys().find(|k| k == &"escaped\n"), Some(Cow::Owned(_))));
+//! ```
+use crate::*;
+use alloc::{
+    borrow::{Cow, ToOwned},
+    string::String,
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+};
+use serde::{
+    de::{self, Deserialize, DeserializeSeed, Deserializer},
+    ser::{Serialize, Serializer},
+};
+
+/// Key types that can borrow from the input they are deserialised from.
+pub trait BorrowKey<'de>: Sized
+{
+    /// Deserialise this key, borrowing from the input if possible.
+    fn deserialize_borrowed<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de>;
+}
+
+impl<'de> BorrowKey<'de> for &'de str
+{
+    #[inline] fn deserialize_borrowed<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de>
+    {
+	Deserialize::deserialize(deserializer)
+    }
+}
+
+impl<'de> BorrowKey<'de> for &'de [u8]
+{
+    #[inline] fn deserialize_borrowed<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de>
+    {
+	Deserialize::deserialize(deserializer)
+    }
+}
+
+impl<'de> BorrowKey<'de> for Cow<'de, str>
+{
+    fn deserialize_borrowed<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de>
+    {
+	struct StrVisitor;
+	impl<'de> de::Visitor<'de> for StrVisitor
+	{
+	    type Value = Cow<'de, str>;
+
+	    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a string")
+	    }
+
+	    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+		Ok(Cow::Borrowed(v))
+	    }
+	    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+		Ok(Cow::Owned(v.to_owned()))
+	    }
+	    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+		Ok(Cow::Owned(v))
+	    }
+	    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+		core::str::from_utf8(v).map(Cow::Borrowed).map_err(|_| E::invalid_value(de::Unexpected::Bytes(v), &self))
+	    }
+	    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+		core::str::from_utf8(v).map(|s| Cow::Owned(s.to_owned())).map_err(|_| E::invalid_value(de::Unexpected::Bytes(v), &self))
+	    }
+	}
+	deserializer.deserialize_str(StrVisitor)
+    }
+}
+
+impl<'de> BorrowKey<'de> for Cow<'de, [u8]>
+{
+    fn deserialize_borrowed<D>(deserializer: D) -> Result<Self, D::Error>
+    where D: Deserializer<'de>
+    {
+	struct BytesVisitor;
+	impl<'de> de::Visitor<'de> for BytesVisitor
+	{
+	    type Value = Cow<'de, [u8]>;
+
+	    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+		formatter.write_str("a byte array")
+	    }
+
+	    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+		Ok(Cow::Borrowed(v))
+	    }
+	    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+		Ok(Cow::Owned(v.to_owned()))
+	    }
+	    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+		Ok(Cow::Owned(v))
+	    }
+	    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+		Ok(Cow::Borrowed(v.as_bytes()))
+	    }
+	    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+		Ok(Cow::Owned(v.as_bytes().to_owned()))
+	    }
+	    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+		let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+		while let Some(byte) = seq.next_element()? {
+		    bytes.push(byte);
+		}
+		Ok(Cow::Owned(bytes))
+	    }
+	}
+	deserializer.deserialize_bytes(BytesVisitor)
+    }
+}
+
+/// Serialise `map`. This is identical to `Map`'s `Serialize` impl.
+#[inline] pub fn serialize<K, V, S>(map: &Map<K,V>, serializer: S) -> Result<S::Ok, S::Error>
+where K: Collapse + Serialize,
+      V: Serialize,
+      S: Serializer
+{
+    map.serialize(serializer)
+}
+
+/// Deserialise a `Map` whose keys borrow from the input if possible.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Map<K,V>, D::Error>
+where K: Collapse + BorrowKey<'de>,
+      V: Deserialize<'de>,
+      D: Deserializer<'de>
+{
+    deserializer.deserialize_map(BorrowVisitor(PhantomData))
+}
+
+struct KeySeed<K>(PhantomData<K>);
+
+impl<'de, K: BorrowKey<'de>> DeserializeSeed<'de> for KeySeed<K>
+{
+    type Value = K;
+
+    #[inline] fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where D: Deserializer<'de>
+    {
+	K::deserialize_borrowed(deserializer)
+    }
+}
+
+struct BorrowVisitor<K,V>(PhantomData<Map<K,V>>);
+
+impl<'de, K, V> de::Visitor<'de> for BorrowVisitor<K,V>
+where K: Collapse + BorrowKey<'de>,
+      V: Deserialize<'de>
+{
+    type Value = Map<K,V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+	formatter.write_str("A map")
+    }
+
+    fn visit_map<A>(self, mut access: A) -> Result<Self::Value, A::Error> where A: de::MapAccess<'de> {
+	let mut map = Map::with_capacity(access.size_hint().unwrap_or(0) / MAX + 1);
+	while let Some(key) = access.next_key_seed(KeySeed(PhantomData))? {
+	    map.insert(key, access.next_value()?);
+	}
+	Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use serde::de::value::{
+	BorrowedBytesDeserializer,
+	Error,
+	MapDeserializer,
+    };
+
+    #[test]
+    fn borrowed_str()
+    {
+	let map: Map<&str, i32> = serde_json::from_str(r#"{"one": 1, "two": 2}"#).expect("de failed");
+	assert_eq!(map.get("two"), Some(&2));
+
+	// Escaped keys cannot be borrowed
+	assert!(serde_json::from_str::<Map<&str, i32>>(r#"{"one\n": 1}"#).is_err());
+    }
+
+    #[test]
+    fn borrowed_bytes()
+    {
+	let input: [&[u8]; 2] = [b"one", b"two"];
+	let de = MapDeserializer::<_, Error>::new(input.iter().zip(1..).map(|(&k, v)| (BorrowedBytesDeserializer::new(k), v)));
+	let map: Map<&[u8], i32> = Deserialize::deserialize(de).expect("de failed");
+	assert_eq!(map.get(&b"two"[..]), Some(&2));
+	assert!(core::ptr::eq(*map.get_key_value(&b"one"[..]).unwrap().0, input[0]));
+
+	let de = MapDeserializer::<_, Error>::new(input.iter().zip(1..).map(|(&k, v)| (BorrowedBytesDeserializer::new(k), v)));
+	let map: Map<Cow<[u8]>, i32> = deserialize(de).expect("de failed");
+	assert!(map.keys().all(|k| matches!(k, Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn cow_str()
+    {
+	let json = r#"{"one": 1, "two\t": 2}"#;
+
+	let map: Map<Cow<str>, i32> = serde_json::from_str(json).expect("de failed");
+	assert!(map.keys().all(|k| matches!(k, Cow::Owned(_))));
+
+	let map: Map<Cow<str>, i32> = deserialize(&mut serde_json::Deserializer::from_str(json)).expect("de failed");
+	assert_eq!(map.get("one"), Some(&1));
+	assert_eq!(map.get("two\t"), Some(&2));
+	assert!(matches!(map.get_key_value("one"), Some((Cow::Borrowed(_), _))));
+	assert!(matches!(map.get_key_value("two\t"), Some((Cow::Owned(_), _))));
+    }
+
+    #[test]
+    fn in_place()
+    {
+	let mut map: Map<u16, u16> = (0..600).map(|x| (x, x)).collect();
+	let pages = map.num_pages();
+	let ptr = map.pages().next().unwrap() as *const Page<_,_>;
+
+	Deserialize::deserialize_in_place(&mut serde_json::Deserializer::from_str(r#"{"1": 10, "2": 20}"#), &mut map).expect("de failed");
+	assert_eq!(map.len(), 2);
+	assert_eq!(map.get(&2), Some(&20));
+	assert_eq!(map.num_pages(), pages);
+	assert!(core::ptr::eq(map.pages().next().unwrap(), ptr));
+    }
+}
@@ -0,0 +1,198 @@
+//! Layout-preserving serialisation of `Map`s
+//!
+//! This is used with `#[serde(with = "smallmap::serde_layout")]` on fields of `Map` type.
+//!
+//! The default `Serialize` impl of `Map` writes a plain map of its entries, which are reinserted on load; so the page layout (and thus iteration order) of the loaded map may differ from the original.
+//! This representation instead writes each page (in the compact form of `Page`'s `Serialize` impl, see [`Page`](crate::Page)) along with a format [`VERSION`], and restores the pages as they were without reinserting the entries.
+//! The loaded pages are checked with [`Map::validate()`](crate::Map::validate), and an error is returned if they are invalid.
+//!
+//! # Usage
+//! ```
+//! # use smallmap::*;
+//! #[derive(serde::Serialize, serde::Deserialize)]
+//! struct Table
+//! {
+//!     #[serde(with = "smallmap::serde_layout")]
+//!     map: Map<u16, char>,
+//! }
+//! let mut map = smallmap!{ {1 => 'a'}, {257 => 'b'} };
+//! map.remove(&1);
+//! map.insert(2, 'c');
+//!
+//! let json = serde_json::to_string(&Table { map: map.clone() }).unwrap();
+//! let table: Table = serde_json::from_str(&json).unwrap();
+//! assert_eq!(table.map, map);
+//! assert!(table.map.iter().eq(map.iter()));
+//! ```
+use crate::*;
+use serde::{
+    de::{self, Deserialize, Deserializer},
+    ser::{Serialize, Serializer, SerializeStruct},
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+};
+
+/// The current version of this format.
+///
+/// Data written with a different version is rejected when deserialising.
+pub const VERSION: u32 = 1;
+
+const FIELDS: &[&str] = &["version", "pages"];
+
+/// Serialise `map` with its page layout.
+pub fn serialize<K, V, S>(map: &Map<K,V>, serializer: S) -> Result<S::Ok, S::Error>
+where K: Serialize,
+      V: Serialize,
+      S: Serializer
+{
+    let mut st = serializer.serialize_struct("Map", FIELDS.len())?;
+    st.serialize_field("version", &VERSION)?;
+    st.serialize_field("pages", &map.0)?;
+    st.end()
+}
+
+/// Deserialise a `Map` with the page layout it was serialised with.
+pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<Map<K,V>, D::Error>
+where K: Collapse + Deserialize<'de>,
+      V: Deserialize<'de>,
+      D: Deserializer<'de>
+{
+    deserializer.deserialize_struct("Map", FIELDS, LayoutVisitor(PhantomData))
+}
+
+#[derive(serde::Deserialize)]
+#[serde(field_identifier, rename_all = "lowercase")]
+enum Field
+{
+    Version,
+    Pages,
+}
+
+struct LayoutVisitor<K,V>(PhantomData<Map<K,V>>);
+
+impl<K: Collapse, V> LayoutVisitor<K,V>
+{
+    fn check_version<E: de::Error>(version: u32) -> Result<(), E>
+    {
+	if version == VERSION {
+	    Ok(())
+	} else {
+	    Err(E::custom(alloc::format!("Unsupported map layout version {} (expected {})", version, VERSION)))
+	}
+    }
+
+    fn build<E: de::Error>(pages: Vec<Page<K,V>>) -> Result<Map<K,V>, E>
+    {
+	let map = Map(pages);
+	map.validate().map_err(E::custom)?;
+	Ok(map)
+    }
+}
+
+impl<'de, K, V> de::Visitor<'de> for LayoutVisitor<K,V>
+where K: Collapse + Deserialize<'de>,
+      V: Deserialize<'de>
+{
+    type Value = Map<K,V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+	formatter.write_str("a map layout with `version` and `pages` fields")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where
+	A: de::SeqAccess<'de>
+    {
+	let version = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+	Self::check_version(version)?;
+	let pages = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+	Self::build(pages)
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where
+	A: de::MapAccess<'de>
+    {
+	let mut version = None;
+	let mut pages = None;
+	while let Some(key) = map.next_key()? {
+	    match key {
+		Field::Version => {
+		    if version.is_some() {
+			return Err(de::Error::duplicate_field("version"));
+		    }
+		    let v = map.next_value()?;
+		    Self::check_version(v)?;
+		    version = Some(v);
+		},
+		Field::Pages => {
+		    if pages.is_some() {
+			return Err(de::Error::duplicate_field("pages"));
+		    }
+		    pages = Some(map.next_value()?);
+		},
+	    }
+	}
+	version.ok_or_else(|| de::Error::missing_field("version"))?;
+	Self::build(pages.ok_or_else(|| de::Error::missing_field("pages"))?)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+    struct Wrapper(#[serde(with = "crate::serde_layout")] Map<u16, u16>);
+
+    #[test]
+    fn round_trip()
+    {
+	let mut map: Map<u16, u16> = (0..600).map(|x| (x, x)).collect();
+	for x in (0..300).step_by(3) {
+	    map.remove(&x);
+	}
+	map.insert(1000, 1);
+
+	let string = serde_json::to_string(&Wrapper(map.clone())).expect("ser failed");
+	let Wrapper(map2) = serde_json::from_str(&string[..]).expect("de failed");
+	assert_eq!(map, map2);
+	assert!(map.iter().eq(map2.iter()));
+    }
+
+    #[test]
+    fn round_trip_reinserted()
+    {
+	let mut map: Map<u16, u16> = Map::new();
+	map.insert(1, 1);
+	map.insert(257, 257);
+	map.remove(&1);
+	map.insert(257, 0);
+	map.insert(1, 1);
+
+	let string = serde_json::to_string(&Wrapper(map.clone())).expect("ser failed");
+	let Wrapper(map2) = serde_json::from_str(&string[..]).expect("de failed");
+	assert_eq!(map2.len(), 2);
+	assert!(map.iter().eq(map2.iter()));
+    }
+
+    #[test]
+    fn version()
+    {
+	assert!(serde_json::from_str::<Wrapper>(r#"{"version":1,"pages":[{}]}"#).is_ok());
+	assert!(serde_json::from_str::<Wrapper>(r#"{"version":2,"pages":[{}]}"#).is_err());
+	assert!(serde_json::from_str::<Wrapper>(r#"{"pages":[{}]}"#).is_err());
+    }
+
+    #[test]
+    fn invalid()
+    {
+	// No pages
+	assert!(serde_json::from_str::<Wrapper>(r#"{"version":1,"pages":[]}"#).is_err());
+	// Duplicate key
+	assert!(serde_json::from_str::<Wrapper>(r#"{"version":1,"pages":[{"1":[1,1]},{"1":[1,2]}]}"#).is_err());
+	// Misplaced key
+	assert!(serde_json::from_str::<Wrapper>(r#"{"version":1,"pages":[{"1":[2,1]}]}"#).is_err());
+    }
+}
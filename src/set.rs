@@ -0,0 +1,541 @@
+//! A set type built on `Map`.
+//!
+//! The API is similar to that of `HashSet`, including the set algebra operations and operator overloads.
+use super::*;
+use core::{
+    fmt::{self, Debug,},
+    hash::{Hash, Hasher,},
+    iter::{
+	Chain,
+	FusedIterator,
+    },
+    ops::{
+	BitOr,
+	BitAnd,
+	BitXor,
+	Sub,
+    },
+};
+
+/// A smallmap set.
+///
+/// Can be used to quickly insert or remove a key only, with no value; and can be used to see if this key is present.
+///
+/// This is a thin wrapper over a `Map` with a zero-sized value, since any such map is essentially a set.
+///
+/// Sets are equal if they contain the same values, regardless of which pages the values are in.
+#[derive(Clone)]
+pub struct Set<T>(Map<T,()>);
+
+impl<T> Set<T>
+{
+    /// A reference to the underlying `Map`
+    #[inline] pub fn as_map(&self) -> &Map<T,()>
+    {
+	&self.0
+    }
+
+    /// Consume this set into the underlying `Map`
+    #[inline] pub fn into_map(self) -> Map<T,()>
+    {
+	self.0
+    }
+}
+
+impl<T> Set<T>
+where T: Collapse
+{
+    /// Create a new empty `Set`
+    #[inline] pub fn new() -> Self
+    {
+	Self(Map::new())
+    }
+
+    /// Create a new empty `Set` with a specific number of pages pre-allocated
+    #[inline] pub fn with_capacity(pages: usize) -> Self
+    {
+	Self(Map::with_capacity(pages))
+    }
+
+    /// The number of values currently in this set
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.0.len()
+    }
+
+    /// Is this set empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.0.is_empty()
+    }
+
+    /// The number of pages currently in this set
+    #[inline] pub fn num_pages(&self) -> usize
+    {
+	self.0.num_pages()
+    }
+
+    /// Remove all empty pages from this instance.
+    #[inline] pub fn clean(&mut self)
+    {
+	self.0.clean()
+    }
+
+    /// An iterator over all values in the set
+    #[inline] pub fn iter(&self) -> Iter<'_, T>
+    {
+	Iter(self.0.iter())
+    }
+
+    /// Is this value present in the set
+    #[inline] pub fn contains<Q>(&self, value: &Q) -> bool
+    where T: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.contains_key(value)
+    }
+
+    /// Get a reference to the value in the set equal to this one, if it is present.
+    #[inline] pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where T: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.get_key_value(value).map(|(k, _)| k)
+    }
+
+    /// Insert a value into the set, returning `true` if it was not already present.
+    ///
+    /// If an equal value is already present, it is not replaced. See [`replace()`](Set::replace).
+    #[inline] pub fn insert(&mut self, value: T) -> bool
+    {
+	self.0.insert(value, ()).is_none()
+    }
+
+    /// Insert a value into the set, replacing and returning the equal value that was present if there was one.
+    pub fn replace(&mut self, value: T) -> Option<T>
+    {
+	for page in (self.0).0.iter_mut()
+	{
+	    match page.search_mut(&value) {
+		Some((ok, _)) if value.eq(ok) => {
+		    return Some(core::mem::replace(ok, value));
+		},
+		_ => (),
+	    }
+	}
+	self.0.insert(value, ());
+	None
+    }
+
+    /// Remove a value from the set, returning `true` if it was present.
+    #[inline] pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where T: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.remove(value).is_some()
+    }
+
+    /// Remove and return the value in the set equal to this one, if it is present.
+    #[inline] pub fn take<Q>(&mut self, value: &Q) -> Option<T>
+    where T: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.0.remove_entry(value).map(|(k, _)| k)
+    }
+
+    /// A lazy iterator over the values that are in `self`, `other`, or both.
+    pub fn union<'a>(&'a self, other: &'a Self) -> Union<'a, T>
+    {
+	Union(self.iter().chain(other.difference(self)))
+    }
+
+    /// A lazy iterator over the values that are in both `self` and `other`.
+    pub fn intersection<'a>(&'a self, other: &'a Self) -> Intersection<'a, T>
+    {
+	Intersection(self.iter(), other)
+    }
+
+    /// A lazy iterator over the values that are in `self` but not in `other`.
+    pub fn difference<'a>(&'a self, other: &'a Self) -> Difference<'a, T>
+    {
+	Difference(self.iter(), other)
+    }
+
+    /// A lazy iterator over the values that are in `self` or `other`, but not in both.
+    pub fn symmetric_difference<'a>(&'a self, other: &'a Self) -> SymmetricDifference<'a, T>
+    {
+	SymmetricDifference(self.difference(other).chain(other.difference(self)))
+    }
+
+    /// Do `self` and `other` have no values in common
+    pub fn is_disjoint(&self, other: &Self) -> bool
+    {
+	self.intersection(other).next().is_none()
+    }
+
+    /// Are all values in `self` also in `other`
+    pub fn is_subset(&self, other: &Self) -> bool
+    {
+	self.iter().all(|x| other.contains(x))
+    }
+
+    /// Are all values in `other` also in `self`
+    #[inline] pub fn is_superset(&self, other: &Self) -> bool
+    {
+	other.is_subset(self)
+    }
+}
+
+impl<T: Collapse> Default for Set<T>
+{
+    #[inline]
+    fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<T: Collapse> PartialEq for Set<T>
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+	self.len() == other.len() && self.iter().all(|value| other.contains(value))
+    }
+}
+
+impl<T: Collapse> Eq for Set<T>{}
+
+impl<T: Hash> Hash for Set<T>
+{
+    #[inline] fn hash<H: Hasher>(&self, state: &mut H)
+    {
+	self.0.hash(state)
+    }
+}
+
+impl<T: Debug> Debug for Set<T>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_set().entries((self.0).0.iter().flat_map(|page| page.0.iter().flatten()).map(|(k, _)| k)).finish()
+    }
+}
+
+impl<T: Collapse> From<Map<T,()>> for Set<T>
+{
+    #[inline] fn from(from: Map<T,()>) -> Self
+    {
+	Self(from)
+    }
+}
+
+impl<T: Collapse> core::iter::FromIterator<T> for Set<T>
+{
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Self
+    {
+	Self(iter.into_iter().map(|x| (x, ())).collect())
+    }
+}
+
+impl<T: Collapse> core::iter::Extend<T> for Set<T>
+{
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+	self.0.extend(iter.into_iter().map(|x| (x, ())))
+    }
+}
+
+impl<T: Collapse> IntoIterator for Set<T>
+{
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    /// Consume this set into an iterator over all currently inserted values
+    fn into_iter(self) -> Self::IntoIter
+    {
+	IntoIter(self.0.into_iter())
+    }
+}
+
+impl<'a, T: Collapse> IntoIterator for &'a Set<T>
+{
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+
+macro_rules! set_op {
+    ($trait:ident, $fn:ident, $via:ident, $doc:literal) => {
+	impl<'a, 'b, T> $trait<&'b Set<T>> for &'a Set<T>
+	where T: Collapse + Clone
+	{
+	    type Output = Set<T>;
+
+	    #[doc = $doc]
+	    fn $fn(self, rhs: &'b Set<T>) -> Self::Output
+	    {
+		self.$via(rhs).cloned().collect()
+	    }
+	}
+    };
+}
+
+set_op!(BitOr, bitor, union, "Returns the union of `self` and `rhs` as a new `Set`.");
+set_op!(BitAnd, bitand, intersection, "Returns the intersection of `self` and `rhs` as a new `Set`.");
+set_op!(Sub, sub, difference, "Returns the difference of `self` and `rhs` as a new `Set`.");
+set_op!(BitXor, bitxor, symmetric_difference, "Returns the symmetric difference of `self` and `rhs` as a new `Set`.");
+
+/// An iterator over values in a `Set`.
+pub struct Iter<'a, T>(iter::Iter<'a, T, ()>);
+
+impl<'a, T: Collapse> Iterator for Iter<'a, T>
+{
+    type Item = &'a T;
+
+    #[inline] fn next(&mut self) -> Option<Self::Item> {
+	self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	self.0.size_hint()
+    }
+}
+impl<'a, T: Collapse> FusedIterator for Iter<'a, T>{}
+
+/// A consuming iterator over values in a `Set`.
+pub struct IntoIter<T>(iter::IntoIter<T, ()>);
+
+impl<T: Collapse> Iterator for IntoIter<T>
+{
+    type Item = T;
+
+    #[inline] fn next(&mut self) -> Option<Self::Item> {
+	self.0.next().map(|(k, _)| k)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	self.0.size_hint()
+    }
+}
+impl<T: Collapse> FusedIterator for IntoIter<T>{}
+
+/// A lazy iterator over the union of two `Set`s.
+///
+/// See [`Set::union()`](Set::union).
+pub struct Union<'a, T>(Chain<Iter<'a, T>, Difference<'a, T>>);
+
+impl<'a, T: Collapse> Iterator for Union<'a, T>
+{
+    type Item = &'a T;
+
+    #[inline] fn next(&mut self) -> Option<Self::Item> {
+	self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	self.0.size_hint()
+    }
+}
+impl<'a, T: Collapse> FusedIterator for Union<'a, T>{}
+
+/// A lazy iterator over the intersection of two `Set`s.
+///
+/// See [`Set::intersection()`](Set::intersection).
+pub struct Intersection<'a, T>(Iter<'a, T>, &'a Set<T>);
+
+impl<'a, T: Collapse> Iterator for Intersection<'a, T>
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	let other = self.1;
+	self.0.find(|x| other.contains(*x))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	(0, self.0.size_hint().1)
+    }
+}
+impl<'a, T: Collapse> FusedIterator for Intersection<'a, T>{}
+
+/// A lazy iterator over the difference of two `Set`s.
+///
+/// See [`Set::difference()`](Set::difference).
+pub struct Difference<'a, T>(Iter<'a, T>, &'a Set<T>);
+
+impl<'a, T: Collapse> Iterator for Difference<'a, T>
+{
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+	let other = self.1;
+	self.0.find(|x| !other.contains(*x))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	(0, self.0.size_hint().1)
+    }
+}
+impl<'a, T: Collapse> FusedIterator for Difference<'a, T>{}
+
+/// A lazy iterator over the symmetric difference of two `Set`s.
+///
+/// See [`Set::symmetric_difference()`](Set::symmetric_difference).
+pub struct SymmetricDifference<'a, T>(Chain<Difference<'a, T>, Difference<'a, T>>);
+
+impl<'a, T: Collapse> Iterator for SymmetricDifference<'a, T>
+{
+    type Item = &'a T;
+
+    #[inline] fn next(&mut self) -> Option<Self::Item> {
+	self.0.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+	self.0.size_hint()
+    }
+}
+impl<'a, T: Collapse> FusedIterator for SymmetricDifference<'a, T>{}
+
+#[cfg(feature="serde")]
+const _: () = {
+    use serde::*;
+    use core::marker::PhantomData;
+
+    impl<T> Serialize for Set<T>
+    where T: Collapse + Serialize
+    {
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+            S: Serializer,
+	{
+	    serializer.collect_seq(self.iter())
+	}
+    }
+
+    struct SetVisitor<T>(PhantomData<Set<T>>);
+
+    impl<'de, T> de::Visitor<'de> for SetVisitor<T>
+    where T: Collapse + Deserialize<'de>
+    {
+	type Value = Set<T>;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a sequence of set values")
+	}
+
+	fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error> where
+	    A: de::SeqAccess<'de>
+	{
+	    let mut set = Set::new();
+	    while let Some(value) = seq.next_element()?
+	    {
+		set.insert(value);
+	    }
+	    Ok(set)
+	}
+    }
+
+    impl<'de, T> Deserialize<'de> for Set<T>
+    where T: Collapse + Deserialize<'de>
+    {
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+            D: Deserializer<'de>,
+	{
+            deserializer.deserialize_seq(SetVisitor(PhantomData))
+	}
+    }
+};
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn sorted<'a>(iter: impl Iterator<Item = &'a i32>) -> Vec<i32>
+    {
+	let mut v: Vec<_> = iter.copied().collect();
+	v.sort();
+	v
+    }
+
+    #[test]
+    fn insert_remove()
+    {
+	let mut set = smallset![1, 2, 3];
+	assert!(!set.insert(2));
+	assert!(set.insert(4));
+	assert_eq!(set.len(), 4);
+	assert!(set.remove(&1));
+	assert!(!set.remove(&1));
+	assert_eq!(set.take(&2), Some(2));
+	assert_eq!(set.get(&3), Some(&3));
+	assert_eq!(set.replace(3), Some(3));
+	assert_eq!(set.replace(5), None);
+	assert_eq!(sorted(set.iter()), [3, 4, 5]);
+    }
+
+    #[test]
+    fn reinsert_after_remove()
+    {
+	let mut set: Set<u16> = Set::new();
+	assert!(set.insert(1));
+	assert!(set.insert(257));
+	assert!(set.remove(&1));
+	assert!(!set.is_empty());
+	assert!(!set.insert(257));
+	assert_eq!(set.len(), 1);
+
+	let a: Set<u16> = [1, 257].iter().copied().collect();
+	let b: Set<u16> = [257, 1].iter().copied().collect();
+	assert_eq!(a, b);
+	assert_ne!(a, set);
+
+	let hash = |set: &Set<u16>| {
+	    let mut state = std::collections::hash_map::DefaultHasher::new();
+	    set.hash(&mut state);
+	    state.finish()
+	};
+	assert_eq!(hash(&a), hash(&b));
+    }
+
+    #[test]
+    fn algebra()
+    {
+	let a: Set<i32> = (0..10).collect();
+	let b: Set<i32> = (5..300).collect();
+
+	assert_eq!(sorted(a.union(&b)), (0..300).collect::<Vec<_>>());
+	assert_eq!(sorted(a.intersection(&b)), (5..10).collect::<Vec<_>>());
+	assert_eq!(sorted(a.difference(&b)), (0..5).collect::<Vec<_>>());
+	assert_eq!(sorted(a.symmetric_difference(&b)), (0..5).chain(10..300).collect::<Vec<_>>());
+
+	assert_eq!(sorted((&a | &b).iter()), sorted(a.union(&b)));
+	assert_eq!(sorted((&a & &b).iter()), sorted(a.intersection(&b)));
+	assert_eq!(sorted((&a - &b).iter()), sorted(a.difference(&b)));
+	assert_eq!(sorted((&a ^ &b).iter()), sorted(a.symmetric_difference(&b)));
+
+	assert!(!a.is_disjoint(&b));
+	assert!((&a - &b).is_disjoint(&b));
+	assert!((&a & &b).is_subset(&a));
+	assert!(b.is_superset(&(&a & &b)));
+	assert!(!a.is_subset(&b));
+    }
+
+    #[cfg(feature="serde")]
+    #[test]
+    fn serde()
+    {
+	let set = smallset!['a', 'b'];
+	let string = serde_json::to_string(&set).expect("ser failed");
+	assert!(string.starts_with('['));
+	let set2: Set<char> = serde_json::from_str(&string[..]).expect("de failed");
+	assert_eq!(set, set2);
+    }
+}
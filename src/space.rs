@@ -58,7 +58,7 @@ mod tests
 	    ($name:ident, $type:ty, $num:expr) => {
 		#[test]
 		fn $name() {
-		    assert_space_of(<$type>::new(), $num);
+		    assert_space_of(<$type>::new().into_map(), $num);
 		}
 	    }
 	}
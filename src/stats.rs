@@ -0,0 +1,204 @@
+//! Collision and load diagnostics for `Map`
+//!
+//! These can be used to check how well a key type collapses before (or after) committing to using it in a `Map`.
+//! See [`Map::stats()`](Map::stats) and [`Map::collapse_quality()`](Map::collapse_quality).
+use super::*;
+use core::mem;
+
+/// Collision and load statistics for a `Map`, or for a set of keys as they would be laid out in one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Stats
+{
+    depths: [usize; MAX],
+    fill: Vec<usize>,
+    slot_size: usize,
+    allocated: usize,
+}
+
+impl Stats
+{
+    /// The number of entries in each slot index across all pages.
+    ///
+    /// Slot `n` has depth `d` if `d` keys collapse to `n`.
+    #[inline] pub fn slot_depths(&self) -> &[usize; MAX]
+    {
+	&self.depths
+    }
+
+    /// A histogram of slot depths.
+    ///
+    /// Element `d` of the returned vector is the number of slot indecies with a depth of exactly `d`. Element `0` is the number of slot indecies that are unused in every page.
+    pub fn depth_histogram(&self) -> Vec<usize>
+    {
+	let mut hist = vec![0usize; self.longest_chain() + 1];
+	for &depth in self.depths.iter() {
+	    hist[depth] += 1;
+	}
+	hist
+    }
+
+    /// The length of the longest chain of colliding keys.
+    ///
+    /// This is the number of pages that must be searched to find the worst-placed key.
+    #[inline] pub fn longest_chain(&self) -> usize
+    {
+	self.depths.iter().copied().max().unwrap_or(0)
+    }
+
+    /// The total number of entries
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.fill.iter().sum()
+    }
+
+    /// Are there no entries
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.len() == 0
+    }
+
+    /// The number of pages used
+    #[inline] pub fn pages(&self) -> usize
+    {
+	self.fill.len()
+    }
+
+    /// The number of occupied slots in each page
+    #[inline] pub fn page_fill(&self) -> &[usize]
+    {
+	&self.fill[..]
+    }
+
+    /// The ratio of occupied slots to total slots in each page, from `0.0` (empty) to `1.0` (full).
+    pub fn fill_ratios(&self) -> impl Iterator<Item = f64> + '_
+    {
+	self.fill.iter().map(|&n| n as f64 / MAX as f64)
+    }
+
+    /// The ratio of occupied slots to total slots across all pages.
+    pub fn fill_ratio(&self) -> f64
+    {
+	match self.pages() {
+	    0 => 0.0,
+	    pages => self.len() as f64 / (pages * MAX) as f64,
+	}
+    }
+
+    /// The number of bytes taken up by empty slots in all pages.
+    #[inline] pub fn wasted_bytes(&self) -> usize
+    {
+	(self.pages() * MAX - self.len()) * self.slot_size
+    }
+
+    /// The allocated size in bytes (including currently unused reserved space.)
+    ///
+    /// See [`Map::internal_size_bytes()`](Map::internal_size_bytes).
+    #[inline] pub fn allocated_bytes(&self) -> usize
+    {
+	self.allocated
+    }
+}
+
+impl<K,V> Map<K,V>
+where K: Collapse
+{
+    /// Compute collision and load statistics for this map's current layout.
+    pub fn stats(&self) -> Stats
+    {
+	let mut depths = [0usize; MAX];
+	let mut fill = Vec::with_capacity(self.num_pages());
+	for page in self.0.iter()
+	{
+	    let mut used = 0;
+	    for (_, depth) in page.0.iter().zip(depths.iter_mut()).filter(|(slot, _)| slot.is_some())
+	    {
+		*depth += 1;
+		used += 1;
+	    }
+	    fill.push(used);
+	}
+	Stats {
+	    depths,
+	    fill,
+	    slot_size: mem::size_of::<Option<(K,V)>>(),
+	    allocated: self.internal_size_bytes(),
+	}
+    }
+
+    /// Compute the statistics a map of this type would have after inserting these keys, without building it.
+    ///
+    /// Keys are assumed to be unique. If a key appears more than once, it is counted more than once.
+    ///
+    /// # Example
+    /// ```
+    /// # use smallmap::*;
+    /// let stats = Map::<char, usize>::collapse_quality("hello world".chars().filter(|&c| c != 'l' && c != 'o'));
+    /// assert_eq!(stats.pages(), 1);
+    /// assert_eq!(stats.longest_chain(), 1);
+    /// ```
+    pub fn collapse_quality<I>(keys: I) -> Stats
+    where I: IntoIterator,
+	  I::Item: Borrow<K>
+    {
+	let mut depths = [0usize; MAX];
+	for key in keys.into_iter() {
+	    depths[usize::from(key.borrow().collapse())] += 1;
+	}
+	let pages = depths.iter().copied().max().unwrap_or(0).max(1);
+	let fill = (0..pages).map(|page| depths.iter().filter(|&&depth| depth > page).count()).collect();
+	Stats {
+	    depths,
+	    fill,
+	    slot_size: mem::size_of::<Option<(K,V)>>(),
+	    allocated: pages * mem::size_of::<Page<K,V>>(),
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn stats()
+    {
+	let map: Map<u16, ()> = (0..600).map(|x| (x, ())).collect();
+	let stats = map.stats();
+
+	assert_eq!(stats.len(), 600);
+	assert_eq!(stats.pages(), map.num_pages());
+	assert_eq!(stats.depth_histogram().iter().sum::<usize>(), MAX);
+	assert_eq!(stats.slot_depths().iter().sum::<usize>(), 600);
+	assert_eq!(stats.page_fill()[0], MAX);
+	assert_eq!(stats.wasted_bytes(), (stats.pages() * MAX - 600) * mem::size_of::<Option<(u16, ())>>());
+	assert_eq!(stats.allocated_bytes(), map.internal_size_bytes());
+    }
+
+    #[test]
+    fn collapse_quality()
+    {
+	let mut map = Map::new();
+	for (i, c) in "The quick brown fox jumped over the lazy dog!".chars().enumerate() {
+	    map.insert(c, i);
+	}
+	let quality = Map::<char, usize>::collapse_quality(map.keys());
+	let stats = map.stats();
+
+	assert_eq!(quality.slot_depths(), stats.slot_depths());
+	assert_eq!(quality.page_fill(), stats.page_fill());
+	assert_eq!(quality.longest_chain(), stats.longest_chain());
+	assert_eq!(quality.wasted_bytes(), stats.wasted_bytes());
+    }
+
+    #[test]
+    fn empty()
+    {
+	let stats = Map::<u8, u8>::new().stats();
+	assert!(stats.is_empty());
+	assert_eq!(stats.pages(), 1);
+	assert_eq!(stats.longest_chain(), 0);
+	assert_eq!(stats.depth_histogram(), [MAX]);
+	assert_eq!(stats.fill_ratio(), 0.0);
+    }
+}
@@ -44,6 +44,27 @@ fn is_empty()
     assert!(map2.is_empty());
 }
 
+#[test]
+fn reinsert_after_remove()
+{
+    // 1 and 257 collapse to the same slot, so 257 lives on page 1 and removing 1 leaves a vacant slot before it
+    let mut map = Map::new();
+    map.insert(1u16, 'a');
+    map.insert(257, 'b');
+    map.remove(&1);
+    assert!(!map.is_empty());
+
+    assert_eq!(map.insert(257, 'c'), Some('b'));
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.get(&257), Some(&'c'));
+    *map.entry(257).or_insert('x') = 'd';
+    assert_eq!(map.len(), 1);
+    assert_eq!(map.get(&257), Some(&'d'));
+
+    map.remove(&257);
+    assert!(map.is_empty());
+}
+
 #[test]
 fn it_works()
 {
@@ -191,6 +212,30 @@ fn get_values_mut() {
     assert_eq!(expect_sum, got_sum);
 }
 
+#[test]
+fn clear_clean() {
+    let mut map: Map<u16, ()> = (0..600).map(|x| (x, ())).collect();
+    let pages = map.num_pages();
+    map.clear();
+    assert!(map.is_empty());
+    assert_eq!(map.num_pages(), pages);
+    map.clean();
+    assert_eq!(map.num_pages(), 1);
+    assert!(map.is_empty());
+}
+
+#[test]
+fn retain() {
+    let mut map: Map<u16, u16> = (0..600).map(|x| (x, x)).collect();
+    map.retain(|&k, v| {
+	*v += 1;
+	k % 2 == 0
+    });
+    assert_eq!(map.len(), 300);
+    assert_eq!(map.get(&4), Some(&5));
+    assert_eq!(map.get(&5), None);
+}
+
 #[cfg(nightly)]
 mod benchmarks
 {
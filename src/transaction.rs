@@ -0,0 +1,297 @@
+//! Atomic batches of modifications to a `Map`
+//!
+//! A [`Transaction`] modifies a `Map` while journaling the original contents of every slot it writes to, so that all of its modifications can be undone and the map's exact previous layout restored.
+//! A transaction that is dropped without being [`commit()`](Transaction::commit)ed (including when unwinding from a panic) is rolled back.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut map = smallmap!{ {'a' => 1}, {'b' => 2} };
+//!
+//! let result: Result<(), &str> = map.transaction(|tx| {
+//!     tx.insert('c', 3);
+//!     tx.remove(&'a');
+//!     Err("oops")
+//! });
+//! assert!(result.is_err());
+//! assert_eq!(map, smallmap!{ {'a' => 1}, {'b' => 2} });
+//! ```
+use super::*;
+use core::ops::Deref;
+
+/// The original contents of a slot or page, before it was modified in a transaction.
+#[derive(Debug)]
+enum Undo<K,V>
+{
+    /// The slot at this position contained this entry
+    Slot(usize, u8, Option<(K,V)>),
+    /// A new page was pushed
+    Page,
+}
+
+/// A point in a [`Transaction`] that can be rolled back to.
+///
+/// See [`Transaction::savepoint()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Savepoint(usize);
+
+/// A guard that journals modifications to a `Map` so they can be rolled back.
+///
+/// The map can be read through this guard (it dereferences to `Map`), but can only be modified through its own methods.
+/// If it is dropped without calling [`commit()`](Transaction::commit), all modifications are rolled back.
+///
+/// See [`Map::begin()`](Map::begin) and [`Map::transaction()`](Map::transaction).
+#[derive(Debug)]
+pub struct Transaction<'a, K, V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    map: &'a mut Map<K,V>,
+    journal: Vec<Undo<K,V>>,
+}
+
+impl<K,V> Map<K,V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    /// Begin a transaction on this map.
+    ///
+    /// The modifications made through the transaction are rolled back unless it is committed.
+    #[inline] pub fn begin(&mut self) -> Transaction<'_, K, V>
+    {
+	Transaction {
+	    map: self,
+	    journal: Vec::new(),
+	}
+    }
+
+    /// Run `f` in a transaction on this map, committing it if `f` returns `Ok`, and rolling it back if `f` returns `Err` or panics.
+    pub fn transaction<F, T, E>(&mut self, f: F) -> Result<T, E>
+    where F: FnOnce(&mut Transaction<'_, K, V>) -> Result<T, E>
+    {
+	let mut tx = self.begin();
+	let result = f(&mut tx);
+	if result.is_ok() {
+	    tx.commit();
+	}
+	result
+    }
+}
+
+impl<'a, K, V> Transaction<'a, K, V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    /// Save the original contents of this slot, returning a mutable reference to the slot.
+    fn journal(&mut self, page: usize, slot: u8) -> &mut Option<(K,V)>
+    {
+	let kv = &mut self.map.0[page].0[usize::from(slot)];
+	self.journal.push(Undo::Slot(page, slot, kv.clone()));
+	kv
+    }
+
+    /// Find the page containing the entry for this key
+    fn find<Q>(&self, key: &Q) -> Option<usize>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.map.0.iter().position(|page| matches!(page.search(key), Some((k, _)) if key.eq(k.borrow())))
+    }
+
+    /// Find the slot the entry for this key is in, or would be inserted into, journaling its contents.
+    fn slot_for(&mut self, key: &K) -> &mut Option<(K,V)>
+    {
+	let pages = self.map.num_pages();
+	let page = self.map.page_for(key);
+	if page == pages {
+	    self.journal.push(Undo::Page);
+	}
+	self.journal(page, key.collapse())
+    }
+
+    /// Insert a new key-value entry into the map, returning the pervious value if it was present
+    pub fn insert(&mut self, key: K, value: V) -> Option<V>
+    {
+	self.slot_for(&key).replace((key, value)).map(|(_, v)| v)
+    }
+
+    /// Get an `Entry` for the `key` that lets you get or insert the value
+    ///
+    /// The slot of the entry is journaled whether or not it is modified.
+    pub fn entry(&mut self, key: K) -> Entry<'_, K, V>
+    {
+	let kv = self.slot_for(&key);
+	if kv.is_some() {
+	    Entry::Occupied(entry::OccupiedEntry(kv))
+	} else {
+	    Entry::Vacant(entry::VacantEntry(kv, key))
+	}
+    }
+
+    /// Get a mutable reference of the value corresponding to this key if it is in the map.
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let page = self.find(key)?;
+	self.journal(page, key.collapse()).as_mut().map(|(_, v)| v)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the value if it was present
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.remove_entry(key).map(|(_, v)| v)
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning the key-value pair if it was present
+    pub fn remove_entry<Q>(&mut self, key: &Q) -> Option<(K, V)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let page = self.find(key)?;
+	self.journal(page, key.collapse()).take()
+    }
+
+    /// The number of slot modifications journaled so far
+    #[inline] pub fn journal_len(&self) -> usize
+    {
+	self.journal.len()
+    }
+
+    /// Mark the current state of the transaction, so that later modifications can be undone with [`rollback_to()`](Transaction::rollback_to).
+    #[inline] pub fn savepoint(&self) -> Savepoint
+    {
+	Savepoint(self.journal.len())
+    }
+
+    /// Undo all modifications made since `savepoint` was taken.
+    ///
+    /// Savepoints taken after `savepoint` are invalidated by this, and should not be rolled back to again. Rolling back to a savepoint from a different transaction undoes an unspecified number of modifications.
+    pub fn rollback_to(&mut self, savepoint: Savepoint)
+    {
+	while self.journal.len() > savepoint.0 {
+	    match self.journal.pop() {
+		Some(Undo::Slot(page, slot, kv)) => self.map.0[page].0[usize::from(slot)] = kv,
+		Some(Undo::Page) => {
+		    self.map.0.pop();
+		},
+		None => break,
+	    }
+	}
+    }
+
+    /// Undo all modifications made in this transaction.
+    ///
+    /// This is the same as dropping it.
+    #[inline] pub fn rollback(self){}
+
+    /// Keep all modifications made in this transaction.
+    #[inline] pub fn commit(mut self)
+    {
+	self.journal.clear();
+    }
+}
+
+impl<'a, K, V> Deref for Transaction<'a, K, V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    type Target = Map<K,V>;
+
+    #[inline] fn deref(&self) -> &Self::Target
+    {
+	self.map
+    }
+}
+
+impl<'a, K, V> Drop for Transaction<'a, K, V>
+where K: Collapse + Clone,
+      V: Clone
+{
+    fn drop(&mut self)
+    {
+	self.rollback_to(Savepoint(0));
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn commit()
+    {
+	let mut map = smallmap!{ {1u16 => 'a'} };
+	let mut tx = map.begin();
+	tx.insert(257, 'b');
+	*tx.entry(2).or_insert('c') = 'd';
+	assert_eq!(tx.get(&2), Some(&'d'));
+	tx.commit();
+	assert_eq!(map.len(), 3);
+	assert_eq!(map.num_pages(), 2);
+	assert_eq!(map.get(&2), Some(&'d'));
+    }
+
+    #[test]
+    fn rollback_layout()
+    {
+	let mut map: Map<u16, u16> = (0..600).map(|x| (x, x)).collect();
+	map.remove(&3);
+	let original = map.clone();
+
+	let result: Result<(), ()> = map.transaction(|tx| {
+	    tx.remove(&1);
+	    tx.insert(3, 30);
+	    tx.insert(1000, 1000);
+	    *tx.get_mut(&2).unwrap() = 20;
+	    tx.entry(1100).or_insert(1100);
+	    *tx.entry(4).or_default() += 1;
+	    assert_eq!(tx.num_pages(), 4);
+	    // Page 0's slot 1 is filled by another key, so that re-inserting 1 moves it to a later page
+	    tx.insert(769, 769);
+	    tx.insert(1, 1);
+	    Err(())
+	});
+	assert_eq!(result, Err(()));
+	assert_eq!(map, original);
+	assert_eq!(map.num_pages(), 3);
+	// Equality ignores layout, so check that the entries are back in the same pages as well
+	assert!(map.iter().eq(original.iter()));
+	assert_eq!(map.layout().to_string(), original.layout().to_string());
+	assert!(map.pages().next().unwrap().iter().any(|&(k, _)| k == 1));
+    }
+
+    #[test]
+    fn savepoints()
+    {
+	let mut map = smallmap!{ {'a' => 1} };
+	let mut tx = map.begin();
+	tx.insert('b', 2);
+	let sp = tx.savepoint();
+	tx.insert('c', 3);
+	tx.remove(&'a');
+	tx.rollback_to(sp);
+	assert_eq!(tx.get(&'a'), Some(&1));
+	assert_eq!(tx.get(&'c'), None);
+	assert_eq!(tx.journal_len(), 1);
+	tx.commit();
+	assert_eq!(map, smallmap!{ {'a' => 1}, {'b' => 2} });
+    }
+
+    #[test]
+    fn panic()
+    {
+	let mut map = smallmap!{ {'a' => 1} };
+	let result = std::panic::catch_unwind(core::panic::AssertUnwindSafe(|| {
+	    map.transaction(|tx| -> Result<(), ()> {
+		tx.insert('b', 2);
+		panic!("oops")
+	    })
+	}));
+	assert!(result.is_err());
+	assert_eq!(map, smallmap!{ {'a' => 1} });
+    }
+}
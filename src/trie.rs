@@ -0,0 +1,480 @@
+//! A map keyed by byte strings, that never collides
+//!
+//! Byte string keys in a `Map` collapse to a slot by XOR-folding all of their bytes, so e.g. `"ab"` and `"ba"` always collide, as do many keys with long common prefixes.
+//! A [`TrieMap`] instead indexes a `Page` with each successive byte of the key, so every key has its own path through the trie.
+//! Chains of nodes that have only a single child and no value are compressed into one node.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! let mut routes = TrieMap::new();
+//! routes.insert("/", "index");
+//! routes.insert("/static/", "files");
+//! routes.insert("/static/style.css", "style");
+//!
+//! assert_eq!(routes.get("/static/"), Some(&"files"));
+//! assert_eq!(routes.longest_prefix("/static/logo.png"), Some((&b"/static/"[..], &"files")));
+//! assert_eq!(routes.iter_prefix("/static").count(), 2);
+//! ```
+use super::*;
+use alloc::boxed::Box;
+use core::fmt;
+
+/// A node in a `TrieMap`.
+#[derive(Clone)]
+struct Node<V>
+{
+    /// The bytes of the key consumed by this node, after the byte that indexed it in its parent
+    prefix: Vec<u8>,
+    /// The value of the key ending at this node
+    value: Option<V>,
+    /// The children of this node, indexed by the next byte of the key
+    children: Option<Box<Page<u8, Box<Node<V>>>>>,
+}
+
+impl<V> Node<V>
+{
+    #[inline] fn leaf(prefix: &[u8], value: Option<V>) -> Self
+    {
+	Self {
+	    prefix: prefix.to_vec(),
+	    value,
+	    children: None,
+	}
+    }
+
+    #[inline] fn child(&self, byte: u8) -> Option<&Node<V>>
+    {
+	self.children.as_ref()?.0[usize::from(byte)].as_ref().map(|(_, node)| &**node)
+    }
+
+    #[inline] fn child_mut(&mut self, byte: u8) -> Option<&mut Node<V>>
+    {
+	self.children.as_mut()?.0[usize::from(byte)].as_mut().map(|(_, node)| &mut **node)
+    }
+
+    /// Insert a new child, creating the page of children if needed
+    fn insert_child(&mut self, byte: u8, node: Node<V>)
+    {
+	self.children.get_or_insert_with(|| Box::new(Page::new())).replace(byte, Box::new(node));
+    }
+
+    /// Split this node so that it consumes only the first `at` bytes of its prefix, moving its value and children to a new child.
+    fn split(&mut self, at: usize)
+    {
+	let byte = self.prefix[at];
+	let child = Node {
+	    prefix: self.prefix[at + 1..].to_vec(),
+	    value: self.value.take(),
+	    children: self.children.take(),
+	};
+	self.prefix.truncate(at);
+	self.insert_child(byte, child);
+    }
+
+    /// Remove empty children, and merge this node with its child if it has no value and only one child.
+    fn compress(&mut self)
+    {
+	if let Some(children) = &mut self.children {
+	    for slot in children.0.iter_mut() {
+		if matches!(slot, Some((_, node)) if node.value.is_none() && node.children.is_none()) {
+		    *slot = None;
+		}
+	    }
+	    let mut occupied = children.iter();
+	    match (occupied.next(), occupied.next()) {
+		(None, _) => self.children = None,
+		(Some(&(byte, _)), None) if self.value.is_none() => {
+		    let child = *self.children.take().and_then(|mut children| children.0[usize::from(byte)].take()).map(|(_, node)| node).unwrap();
+		    self.prefix.push(byte);
+		    self.prefix.extend_from_slice(&child.prefix[..]);
+		    self.value = child.value;
+		    self.children = child.children;
+		},
+		_ => (),
+	    }
+	}
+    }
+
+    fn remove(&mut self, key: &[u8]) -> Option<V>
+    {
+	let rest = key.strip_prefix(&self.prefix[..])?;
+	let value = match rest.split_first() {
+	    None => self.value.take(),
+	    Some((&byte, rest)) => self.child_mut(byte)?.remove(rest),
+	};
+	if value.is_some() {
+	    self.compress();
+	}
+	value
+    }
+
+    fn count(&self) -> usize
+    {
+	1 + self.children.iter().flat_map(|children| children.iter()).map(|(_, node)| node.count()).sum::<usize>()
+    }
+}
+
+/// A map from byte strings to values, stored as a trie of `Page`s indexed by each byte of the key.
+///
+/// Keys can be of any type that can be referenced as bytes (e.g. `&str`, `&[u8]`, `String` and `Vec<u8>`), and are yielded by iterators as `Vec<u8>`.
+/// Iteration is in lexicographic order of the keys' bytes.
+#[derive(Clone)]
+pub struct TrieMap<V>
+{
+    root: Node<V>,
+    len: usize,
+}
+
+impl<V> TrieMap<V>
+{
+    /// Create a new empty `TrieMap`
+    #[inline] pub fn new() -> Self
+    {
+	Self {
+	    root: Node::leaf(&[], None),
+	    len: 0,
+	}
+    }
+
+    /// The number of entries in the map
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.len
+    }
+
+    /// Is the map empty
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.len == 0
+    }
+
+    /// The number of nodes in the trie, including the root.
+    ///
+    /// Chains of nodes without values are compressed, so this is at most twice the number of entries, plus the root.
+    pub fn num_nodes(&self) -> usize
+    {
+	self.root.count()
+    }
+
+    /// Find the node for this key
+    fn find(&self, key: &[u8]) -> Option<&Node<V>>
+    {
+	let mut node = &self.root;
+	let mut rest = key.strip_prefix(&node.prefix[..])?;
+	while let Some((&byte, next)) = rest.split_first() {
+	    node = node.child(byte)?;
+	    rest = next.strip_prefix(&node.prefix[..])?;
+	}
+	Some(node)
+    }
+
+    /// Get a reference to the value corresponding to this key if it is in the map
+    #[inline] pub fn get<K: ?Sized + AsRef<[u8]>>(&self, key: &K) -> Option<&V>
+    {
+	self.find(key.as_ref())?.value.as_ref()
+    }
+
+    /// Get a mutable reference to the value corresponding to this key if it is in the map
+    pub fn get_mut<K: ?Sized + AsRef<[u8]>>(&mut self, key: &K) -> Option<&mut V>
+    {
+	let mut node = &mut self.root;
+	let mut rest = key.as_ref().strip_prefix(&node.prefix[..])?;
+	while let Some((&byte, next)) = rest.split_first() {
+	    node = node.child_mut(byte)?;
+	    rest = next.strip_prefix(&node.prefix[..])?;
+	}
+	node.value.as_mut()
+    }
+
+    /// Is this key in the map
+    #[inline] pub fn contains_key<K: ?Sized + AsRef<[u8]>>(&self, key: &K) -> bool
+    {
+	self.get(key).is_some()
+    }
+
+    /// Insert a new entry into the map, returning the previous value if it was present
+    pub fn insert<K: AsRef<[u8]>>(&mut self, key: K, value: V) -> Option<V>
+    {
+	let mut node = &mut self.root;
+	let mut rest = key.as_ref();
+	loop {
+	    if node.value.is_none() && node.children.is_none() {
+		// Only an empty root can have neither, so the whole key can go in it.
+		node.prefix = rest.to_vec();
+		node.value = Some(value);
+		self.len += 1;
+		return None;
+	    }
+	    let common = node.prefix.iter().zip(rest.iter()).take_while(|(a, b)| a == b).count();
+	    if common < node.prefix.len() {
+		node.split(common);
+	    }
+	    rest = &rest[common..];
+	    match rest.split_first() {
+		None => {
+		    let old = node.value.replace(value);
+		    if old.is_none() {
+			self.len += 1;
+		    }
+		    return old;
+		},
+		Some((&byte, next)) => {
+		    if node.child(byte).is_none() {
+			node.insert_child(byte, Node::leaf(next, Some(value)));
+			self.len += 1;
+			return None;
+		    }
+		    node = node.child_mut(byte).unwrap();
+		    rest = next;
+		},
+	    }
+	}
+    }
+
+    /// Remove the entry corresponding to this key in the map, returning its value if it was present
+    pub fn remove<K: ?Sized + AsRef<[u8]>>(&mut self, key: &K) -> Option<V>
+    {
+	let value = self.root.remove(key.as_ref())?;
+	self.len -= 1;
+	Some(value)
+    }
+
+    /// Remove all entries from the map
+    #[inline] pub fn clear(&mut self)
+    {
+	*self = Self::new();
+    }
+
+    /// Find the entry whose key is the longest prefix of `key`, returning that prefix of `key` and its value.
+    pub fn longest_prefix<'k, K: ?Sized + AsRef<[u8]>>(&self, key: &'k K) -> Option<(&'k [u8], &V)>
+    {
+	let key = key.as_ref();
+	let mut node = &self.root;
+	let mut rest = key;
+	let mut longest = None;
+	loop {
+	    rest = match rest.strip_prefix(&node.prefix[..]) {
+		Some(rest) => rest,
+		None => break,
+	    };
+	    if let Some(value) = &node.value {
+		longest = Some((&key[..key.len() - rest.len()], value));
+	    }
+	    match rest.split_first().and_then(|(&byte, next)| Some((node.child(byte)?, next))) {
+		Some((child, next)) => {
+		    node = child;
+		    rest = next;
+		},
+		None => break,
+	    }
+	}
+	longest
+    }
+
+    /// An iterator over all entries in the map
+    #[inline] pub fn iter(&self) -> Iter<'_, V>
+    {
+	self.iter_prefix(&[])
+    }
+
+    /// An iterator over all entries whose keys start with `prefix`
+    pub fn iter_prefix<K: ?Sized + AsRef<[u8]>>(&self, prefix: &K) -> Iter<'_, V>
+    {
+	let mut node = &self.root;
+	let mut path = Vec::new();
+	let mut rest = prefix.as_ref();
+	loop {
+	    let common = node.prefix.iter().zip(rest.iter()).take_while(|(a, b)| a == b).count();
+	    if common == rest.len() {
+		// The prefix ends in this node, so all keys below it match.
+		return Iter(vec![(node, path)]);
+	    }
+	    if common < node.prefix.len() {
+		return Iter(Vec::new());
+	    }
+	    path.extend_from_slice(&node.prefix[..]);
+	    let byte = rest[common];
+	    node = match node.child(byte) {
+		Some(child) => child,
+		None => return Iter(Vec::new()),
+	    };
+	    path.push(byte);
+	    rest = &rest[common + 1..];
+	}
+    }
+
+    /// An iterator over all keys in the map
+    #[inline] pub fn keys(&self) -> impl Iterator<Item = Vec<u8>> + '_
+    {
+	self.iter().map(|(k, _)| k)
+    }
+
+    /// An iterator over all values in the map
+    #[inline] pub fn values(&self) -> impl Iterator<Item = &V>
+    {
+	self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<V> Default for TrieMap<V>
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new()
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for TrieMap<V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V: PartialEq> PartialEq for TrieMap<V>
+{
+    fn eq(&self, other: &Self) -> bool
+    {
+	self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<V: Eq> Eq for TrieMap<V>{}
+
+impl<K: ?Sized + AsRef<[u8]>, V> core::ops::Index<&K> for TrieMap<V>
+{
+    type Output = V;
+
+    fn index(&self, key: &K) -> &Self::Output
+    {
+	self.get(key).expect("Key not found")
+    }
+}
+
+impl<K: AsRef<[u8]>, V> core::iter::FromIterator<(K, V)> for TrieMap<V>
+{
+    fn from_iter<I: IntoIterator<Item=(K, V)>>(iter: I) -> Self
+    {
+	let mut this = Self::new();
+	this.extend(iter);
+	this
+    }
+}
+
+impl<K: AsRef<[u8]>, V> core::iter::Extend<(K, V)> for TrieMap<V>
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I)
+    {
+	for (key, value) in iter.into_iter()
+	{
+	    self.insert(key, value);
+	}
+    }
+}
+
+impl<'a, V> IntoIterator for &'a TrieMap<V>
+{
+    type Item = (Vec<u8>, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    #[inline] fn into_iter(self) -> Self::IntoIter
+    {
+	self.iter()
+    }
+}
+
+/// An iterator over the entries of a [`TrieMap`], in lexicographic order of their keys.
+pub struct Iter<'a, V>(Vec<(&'a Node<V>, Vec<u8>)>);
+
+impl<'a, V> Iterator for Iter<'a, V>
+{
+    type Item = (Vec<u8>, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item>
+    {
+	while let Some((node, mut path)) = self.0.pop() {
+	    path.extend_from_slice(&node.prefix[..]);
+	    if let Some(children) = &node.children {
+		for (byte, child) in children.0.iter().rev().flatten() {
+		    let mut path = path.clone();
+		    path.push(*byte);
+		    self.0.push((child, path));
+		}
+	    }
+	    if let Some(value) = &node.value {
+		return Some((path, value));
+	    }
+	}
+	None
+    }
+}
+
+impl<'a, V> core::iter::FusedIterator for Iter<'a, V>{}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn no_collisions()
+    {
+	let mut trie = TrieMap::new();
+	assert_eq!(trie.insert("ab", 1), None);
+	assert_eq!(trie.insert("ba", 2), None);
+	assert_eq!(trie.insert("", 0), None);
+	assert_eq!(trie.insert("ab", 3), Some(1));
+	assert_eq!(trie.len(), 3);
+	assert_eq!(trie.get("ab"), Some(&3));
+	assert_eq!(trie.get("ba"), Some(&2));
+	assert_eq!(trie.get(""), Some(&0));
+	assert_eq!(trie.get("a"), None);
+	assert_eq!(trie.get("abc"), None);
+	*trie.get_mut(&b"ba"[..]).unwrap() += 10;
+	assert_eq!(trie["ba".as_bytes()], 12);
+    }
+
+    #[test]
+    fn compression()
+    {
+	let mut trie = TrieMap::new();
+	trie.insert("abcdef", 1);
+	assert_eq!(trie.num_nodes(), 1);
+	trie.insert("abcxyz", 2);
+	assert_eq!(trie.num_nodes(), 3);
+	trie.insert("abc", 3);
+	assert_eq!(trie.num_nodes(), 3);
+	trie.insert("ab", 4);
+	assert_eq!(trie.num_nodes(), 4);
+
+	assert_eq!(trie.remove("abcxyz"), Some(2));
+	assert_eq!(trie.remove("abcxyz"), None);
+	assert_eq!(trie.remove("abc"), Some(3));
+	assert_eq!(trie.num_nodes(), 2);
+	assert_eq!(trie.get("abcdef"), Some(&1));
+	assert_eq!(trie.remove("ab"), Some(4));
+	assert_eq!(trie.num_nodes(), 1);
+	assert_eq!(trie.get("abcdef"), Some(&1));
+	assert_eq!(trie.remove("abcdef"), Some(1));
+	assert!(trie.is_empty());
+	assert_eq!(trie.num_nodes(), 1);
+    }
+
+    #[test]
+    fn prefixes()
+    {
+	let trie: TrieMap<usize> = ["a", "ab", "abc", "abd", "b", "ba"].iter().zip(0..).map(|(k, v)| (*k, v)).collect();
+	assert_eq!(trie.longest_prefix("abcz"), Some((&b"abc"[..], &2)));
+	assert_eq!(trie.longest_prefix("abz"), Some((&b"ab"[..], &1)));
+	assert_eq!(trie.longest_prefix("z"), None);
+
+	let keys: Vec<_> = trie.iter_prefix("ab").map(|(k, _)| String::from_utf8(k).unwrap()).collect();
+	assert_eq!(keys, ["ab", "abc", "abd"]);
+	assert_eq!(trie.iter_prefix("abc").count(), 1);
+	assert_eq!(trie.iter_prefix("abz").count(), 0);
+	assert_eq!(trie.keys().collect::<Vec<_>>(), [&b"a"[..], b"ab", b"abc", b"abd", b"b", b"ba"]);
+    }
+}
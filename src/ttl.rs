@@ -0,0 +1,381 @@
+//! A map whose entries expire, built on `Map`.
+//!
+//! Time is read from a [`Clock`], so the map works with `std`'s monotonic clock (`SystemClock`, with the `std` feature), a [`ManualClock`] for tests, or any tick source a `no_std` user implements `Clock` for.
+//!
+//! # Example
+//! ```
+//! # use smallmap::*;
+//! # use smallmap::ttl::ManualClock;
+//! let mut sessions = TtlMap::new(ManualClock::new());
+//! sessions.insert("alice", 1, 10);
+//! sessions.insert("bob", 2, 20);
+//!
+//! sessions.clock().advance(15);
+//! assert_eq!(sessions.get("alice"), None);
+//! assert_eq!(sessions.get("bob"), Some(&2));
+//! assert_eq!(sessions.purge_expired(), 1);
+//! ```
+use super::*;
+use core::{
+    cell::Cell,
+    fmt::{self, Debug,},
+};
+
+/// A source of time for a `TtlMap`.
+pub trait Clock
+{
+    /// A point in time. Later instants compare greater.
+    type Instant: Copy + Ord;
+    /// A span of time that can be added to an `Instant`.
+    type Duration;
+
+    /// The current time
+    fn now(&self) -> Self::Instant;
+    /// The instant `duration` after `instant`.
+    fn after(&self, instant: Self::Instant, duration: Self::Duration) -> Self::Instant;
+}
+
+impl<C: ?Sized + Clock> Clock for &C
+{
+    type Instant = C::Instant;
+    type Duration = C::Duration;
+
+    #[inline] fn now(&self) -> Self::Instant
+    {
+	C::now(self)
+    }
+    #[inline] fn after(&self, instant: Self::Instant, duration: Self::Duration) -> Self::Instant
+    {
+	C::after(self, instant, duration)
+    }
+}
+
+/// A clock that counts ticks and only moves when told to.
+///
+/// The time is in a `Cell`, so it can be advanced through a shared reference (e.g. `TtlMap::clock()`).
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Cell<u64>);
+
+impl ManualClock
+{
+    /// A new clock at tick 0
+    #[inline] pub const fn new() -> Self
+    {
+	Self(Cell::new(0))
+    }
+
+    /// Move the clock forward by `ticks`
+    #[inline] pub fn advance(&self, ticks: u64)
+    {
+	self.0.set(self.0.get().saturating_add(ticks));
+    }
+
+    /// Set the current tick
+    #[inline] pub fn set(&self, tick: u64)
+    {
+	self.0.set(tick);
+    }
+}
+
+impl Clock for ManualClock
+{
+    type Instant = u64;
+    type Duration = u64;
+
+    #[inline] fn now(&self) -> u64
+    {
+	self.0.get()
+    }
+    #[inline] fn after(&self, instant: u64, duration: u64) -> u64
+    {
+	instant.saturating_add(duration)
+    }
+}
+
+/// The monotonic `std::time::Instant` clock.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock
+{
+    type Instant = std::time::Instant;
+    type Duration = std::time::Duration;
+
+    #[inline] fn now(&self) -> Self::Instant
+    {
+	std::time::Instant::now()
+    }
+    /// # Panics
+    /// If the result cannot be represented.
+    #[inline] fn after(&self, instant: Self::Instant, duration: Self::Duration) -> Self::Instant
+    {
+	instant + duration
+    }
+}
+
+/// A map whose entries each expire at a deadline.
+///
+/// Expired entries are ignored by lookups and iteration, but keep their slots until they are overwritten, removed, or reclaimed by `purge_expired()`. An entry is expired once the clock reaches its deadline.
+pub struct TtlMap<K, V, C: Clock>
+{
+    map: Map<K, (V, C::Instant)>,
+    clock: C,
+}
+
+impl<K, V, C: Clock> TtlMap<K, V, C>
+where K: Collapse
+{
+    /// Create a new empty `TtlMap` using this clock
+    #[inline] pub fn new(clock: C) -> Self
+    {
+	Self {
+	    map: Map::new(),
+	    clock,
+	}
+    }
+
+    /// The clock of this map
+    #[inline] pub fn clock(&self) -> &C
+    {
+	&self.clock
+    }
+
+    /// A mutable reference to the clock of this map
+    #[inline] pub fn clock_mut(&mut self) -> &mut C
+    {
+	&mut self.clock
+    }
+
+    /// Insert an entry that expires `ttl` from now, returning the previous value of the key if it was present and had not expired.
+    #[inline] pub fn insert(&mut self, key: K, value: V, ttl: C::Duration) -> Option<V>
+    {
+	let deadline = self.clock.after(self.clock.now(), ttl);
+	self.insert_until(key, value, deadline)
+    }
+
+    /// Insert an entry that expires at `deadline`, returning the previous value of the key if it was present and had not expired.
+    pub fn insert_until(&mut self, key: K, value: V, deadline: C::Instant) -> Option<V>
+    {
+	let now = self.clock.now();
+	self.map.insert(key, (value, deadline))
+	    .filter(|&(_, old)| old > now)
+	    .map(|(v, _)| v)
+    }
+
+    /// The entry for this key, if it has not expired
+    #[inline] fn live<Q>(&self, key: &Q) -> Option<&(V, C::Instant)>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let now = self.clock.now();
+	self.map.get(key).filter(|(_, deadline)| *deadline > now)
+    }
+
+    /// The value of this key, if it is present and has not expired
+    #[inline] pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.live(key).map(|(v, _)| v)
+    }
+
+    /// A mutable reference to the value of this key, if it is present and has not expired
+    #[inline] pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let now = self.clock.now();
+	self.map.get_mut(key).filter(|(_, deadline)| *deadline > now).map(|(v, _)| v)
+    }
+
+    /// Is this key present and not expired
+    #[inline] pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.live(key).is_some()
+    }
+
+    /// When this key expires, if it is present and has not expired
+    #[inline] pub fn expires_at<Q>(&self, key: &Q) -> Option<C::Instant>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	self.live(key).map(|&(_, deadline)| deadline)
+    }
+
+    /// Reset the expiry of this key to `ttl` from now, if it is present and has not expired.
+    ///
+    /// # Returns
+    /// If the key was refreshed.
+    pub fn refresh<Q>(&mut self, key: &Q, ttl: C::Duration) -> bool
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let now = self.clock.now();
+	let deadline = self.clock.after(now, ttl);
+	match self.map.get_mut(key) {
+	    Some((_, old)) if *old > now => {
+		*old = deadline;
+		true
+	    },
+	    _ => false,
+	}
+    }
+
+    /// Remove the entry for this key, returning its value if it had not expired.
+    #[inline] pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where K: Borrow<Q>,
+	  Q: ?Sized + Collapse + Eq
+    {
+	let now = self.clock.now();
+	self.map.remove(key).filter(|&(_, deadline)| deadline > now).map(|(v, _)| v)
+    }
+
+    /// The number of entries that have not expired
+    ///
+    /// This is an iterating count over all slots in all current pages, if possible store it in a temporary instead of re-calling it.
+    #[inline] pub fn len(&self) -> usize
+    {
+	self.iter().count()
+    }
+
+    /// Are there no entries that have not expired
+    #[inline] pub fn is_empty(&self) -> bool
+    {
+	self.iter().next().is_none()
+    }
+
+    /// An iterator over all entries that have not expired
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)>
+    {
+	let now = self.clock.now();
+	self.map.iter().filter(move |(_, (_, deadline))| *deadline > now).map(|(k, (v, _))| (k, v))
+    }
+
+    /// Remove all expired entries, and then any pages left empty (see `Map::clean()`).
+    ///
+    /// # Returns
+    /// The number of entries removed.
+    pub fn purge_expired(&mut self) -> usize
+    {
+	let now = self.clock.now();
+	let mut purged = 0;
+	self.map.retain(|_, (_, deadline)| {
+	    let live = *deadline > now;
+	    purged += usize::from(!live);
+	    live
+	});
+	self.map.clean();
+	purged
+    }
+
+    /// Remove all entries
+    #[inline] pub fn clear(&mut self)
+    {
+	self.map.clear()
+    }
+
+    /// The number of pages in the underlying map, including those only holding expired entries
+    #[inline] pub fn num_pages(&self) -> usize
+    {
+	self.map.num_pages()
+    }
+}
+
+impl<K, V, C> Clone for TtlMap<K, V, C>
+where K: Clone,
+      V: Clone,
+      C: Clock + Clone
+{
+    fn clone(&self) -> Self
+    {
+	Self {
+	    map: self.map.clone(),
+	    clock: self.clock.clone(),
+	}
+    }
+}
+
+impl<K, V, C: Clock + Default> Default for TtlMap<K, V, C>
+where K: Collapse
+{
+    #[inline] fn default() -> Self
+    {
+	Self::new(C::default())
+    }
+}
+
+impl<K: Collapse + Debug, V: Debug, C: Clock> Debug for TtlMap<K, V, C>
+{
+    /// Only entries that have not expired are printed.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+	f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn expiry()
+    {
+	let mut map = TtlMap::new(ManualClock::new());
+	assert_eq!(map.insert('a', 1, 10), None);
+	assert_eq!(map.insert('b', 2, 20), None);
+	assert_eq!(map.expires_at(&'a'), Some(10));
+
+	map.clock().set(9);
+	assert_eq!(map.get(&'a'), Some(&1));
+	assert!(map.refresh(&'a', 5));
+	map.clock().advance(5);
+	assert_eq!(map.get(&'a'), None);
+	assert!(!map.refresh(&'a', 5));
+	assert_eq!(map.len(), 1);
+	assert_eq!(format!("{:?}", map), "{'b': 2}");
+
+	// Expired values are not returned when overwritten or removed
+	assert_eq!(map.insert('a', 3, 10), None);
+	assert_eq!(map.insert('a', 4, 10), Some(3));
+	map.clock().set(20);
+	assert_eq!(map.remove(&'b'), None);
+	*map.get_mut(&'a').unwrap() += 1;
+	assert_eq!(map.get(&'a'), Some(&5));
+	map.clock().set(24);
+	assert!(map.is_empty());
+    }
+
+    #[test]
+    fn purge()
+    {
+	let mut map = TtlMap::new(ManualClock::new());
+	for i in 0..600u16 {
+	    map.insert(i, i, if i % 256 == 0 { 100 } else { 10 });
+	}
+	assert_eq!(map.num_pages(), 3);
+	map.clock().advance(10);
+	assert_eq!(map.len(), 3);
+	assert_eq!(map.purge_expired(), 597);
+	// Each of the survivors collapses to slot 0, so they keep one page each
+	assert_eq!(map.num_pages(), 3);
+	map.clock().advance(90);
+	assert_eq!(map.purge_expired(), 3);
+	assert_eq!(map.num_pages(), 1);
+	assert_eq!(map.purge_expired(), 0);
+    }
+
+    #[test]
+    fn borrowed_clock()
+    {
+	let clock = ManualClock::new();
+	let mut map = TtlMap::new(&clock);
+	map.insert("key", (), 1);
+	clock.advance(1);
+	assert!(!map.contains_key("key"));
+    }
+}